@@ -1,30 +1,36 @@
-use crate::{bitboard::{count_bits, Board, CastlingSide}, get_bit, move_gen::generate_moves, moves::move_str, uci::parse_move, START_POSITION, TRICKY_POSITION};
+use crate::{bitboard::{count_bits, get_ls1b_index, Board, CastlingSide}, get_bit, set_bit, pop_bit, Side, move_gen::generate_legal_moves, moves::move_str, uci::parse_move, START_POSITION, TRICKY_POSITION};
 use rand::prelude::*;
-use csv::{ReaderBuilder, WriterBuilder};
-use serde::{Deserialize, Serialize};
+use csv::{ReaderBuilder, StringRecord};
+use flate2::read::MultiGzDecoder;
 use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::str::FromStr;
+use std::thread;
+use std::time::Instant;
 
 
-#[derive(Debug, Deserialize)]
-struct InputRow {
-    fen: String,
-    evaluation: f64,
-}
-
+// Feature layout: 768 piece-square bits and 12 piece counts, followed by
+// White to move, castling rights (K, Q, k, q) and the en passant file (a..h)
+pub const PIECE_FEATURES:usize = 780;
+pub const FEATURE_COUNT:usize = PIECE_FEATURES + 1 + 4 + 8;
 
 pub fn convert_board_to_csv(board: &Board) -> Vec<u8> {
-    let mut result = Vec::with_capacity(785);
+    let mut result = Vec::with_capacity(FEATURE_COUNT);
     for bitboard in board.bitboards {
         result.extend(&convert_bitboard_to_csv(bitboard));
     }
     for bitboard in board.bitboards {
         result.push(count_bits(bitboard) as u8);
     }
-    // result.push(if board.castle & CastlingSide::WK as u8 != 0 {1} else {0});
-    // result.push(if board.castle & CastlingSide::WQ as u8 != 0 {1} else {0});
-    // result.push(if board.castle & CastlingSide::BK as u8 != 0 {1} else {0});
-    // result.push(if board.castle & CastlingSide::BQ as u8 != 0 {1} else {0});
-    // result.push(board.side as u8);
+    result.push(if board.side == Side::White {1} else {0});
+    result.push(if board.castle & CastlingSide::WK as u8 != 0 {1} else {0});
+    result.push(if board.castle & CastlingSide::WQ as u8 != 0 {1} else {0});
+    result.push(if board.castle & CastlingSide::BK as u8 != 0 {1} else {0});
+    result.push(if board.castle & CastlingSide::BQ as u8 != 0 {1} else {0});
+    for file in 0..8 {
+        result.push(if board.en_passant.is_some_and(|square| square % 8 == file) {1} else {0});
+    }
     result
 }
 
@@ -37,54 +43,328 @@ fn convert_bitboard_to_csv(bitboard:u64) -> Vec<u8> {
 }
 
 
-pub fn process_csv(input_file: &str, output_file: &str) -> Result<(), Box<dyn Error>> {
-    // Open the input CSV file for reading
+/*
+    Conversion of a "fen,evaluation" CSV (plain or gzip compressed) into training rows.
+
+    Rows are read in batches which the worker threads convert in parallel, then written
+    in input order, so memory stays bounded whatever the input size. Rows with an
+    invalid FEN or a non numeric evaluation (mate scores such as "#+3") are skipped
+    and counted.
+
+    Output formats:
+        csv         the FEATURE_COUNT features followed by the evaluation, as text
+        binary      PACKED_MAGIC, PACKED_VERSION (u32), then one PACKED_RECORD_SIZE
+                    record per position (little endian):
+                        occupancy       u64, every occupied square
+                        pieces          16 bytes, the piece (0..12) on each occupied
+                                        square in square order, 4 bits each, low first
+                        flags           u8, bit 0 White to move, bits 1..4 castling rights
+                        en passant      u8, square or 255
+                        evaluation      f32
+*/
+pub const PACKED_MAGIC:&[u8;4] = b"CHPK";
+pub const PACKED_VERSION:u32 = 1;
+pub const PACKED_RECORD_SIZE:usize = 30;
+
+const NO_EN_PASSANT:u8 = 255;
+
+// Rows converted by each thread per batch
+const BATCH_ROWS_PER_THREAD:usize = 16384;
+// Rows between two progress lines
+const PROGRESS_INTERVAL:usize = 1_000_000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    Csv,
+    Binary,
+}
+
+impl OutputFormat {
+    // Binary for ".bin" files, CSV otherwise
+    pub fn from_path(path:&str) -> OutputFormat {
+        if path.ends_with(".bin") {OutputFormat::Binary} else {OutputFormat::Csv}
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format:&str) -> Result<OutputFormat, String> {
+        match format.to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "bin" | "binary" => Ok(OutputFormat::Binary),
+            _ => Err(format!("unknown format {} (expected csv or binary)", format)),
+        }
+    }
+}
+
+pub fn pack_board(board:&Board, evaluation:f32) -> Option<[u8;PACKED_RECORD_SIZE]> {
+    let mut record = [0;PACKED_RECORD_SIZE];
+    let occupancy = board.bitboards.iter().fold(0, |occupancy, bitboard| occupancy | bitboard);
+    if count_bits(occupancy) > 32 {
+        return None
+    }
+
+    record[0..8].copy_from_slice(&occupancy.to_le_bytes());
+    let mut bb = occupancy;
+    let mut index = 0;
+    while bb != 0 {
+        let square = get_ls1b_index(bb);
+        let piece = (0..12).find(|&piece| get_bit!(board.bitboards[piece], square) != 0)?;
+        record[8 + index/2] |= (piece as u8) << (4 * (index % 2));
+        index += 1;
+        pop_bit!(bb, square);
+    }
+
+    record[24] = (if board.side == Side::White {1} else {0}) | (board.castle << 1);
+    record[25] = board.en_passant.map_or(NO_EN_PASSANT, |square| square as u8);
+    record[26..30].copy_from_slice(&evaluation.to_le_bytes());
+    Some(record)
+}
+
+pub fn unpack_board(record:&[u8]) -> Result<(Board, f32), Box<dyn Error>> {
+    let mut board = Board::new();
+    let occupancy = u64::from_le_bytes(record[0..8].try_into()?);
+
+    let mut bb = occupancy;
+    let mut index = 0;
+    while bb != 0 {
+        let square = get_ls1b_index(bb);
+        let piece = ((record[8 + index/2] >> (4 * (index % 2))) & 0xF) as usize;
+        if piece >= 12 {
+            return Err("corrupted packed position".into())
+        }
+        set_bit!(board.bitboards[piece], square);
+        index += 1;
+        pop_bit!(bb, square);
+    }
+
+    board.side = if record[24] & 1 != 0 {Side::White} else {Side::Black};
+    board.castle = (record[24] >> 1) & 0xF;
+    board.en_passant = if record[25] == NO_EN_PASSANT {None} else {Some(record[25] as usize)};
+    for piece in 0..12 {
+        board.occupancies[piece / 6] |= board.bitboards[piece];
+    }
+    board.occupancies[2] = board.occupancies[0] | board.occupancies[1];
+
+    Ok((board, f32::from_le_bytes(record[26..30].try_into()?)))
+}
+
+pub fn is_packed_file(path:&str) -> io::Result<bool> {
+    let mut magic = [0;4];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == PACKED_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Calls visit on every position of a binary file, returns the number of positions
+pub fn read_packed(path:&str, mut visit:impl FnMut(&Board, f32)) -> Result<usize, Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0;8];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != PACKED_MAGIC {
+        return Err("not a packed position file".into())
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into()?);
+    if version != PACKED_VERSION {
+        return Err(format!("unsupported packed file version {}", version).into())
+    }
+
+    let mut record = [0;PACKED_RECORD_SIZE];
+    let mut count = 0;
+    loop {
+        match reader.read_exact(&mut record) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let (board, evaluation) = unpack_board(&record)?;
+        visit(&board, evaluation);
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Plain or gzip input, told apart by the gzip magic bytes
+pub fn open_input(path:&str) -> io::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+// Placement, side to move, castling and en passant fields, checked before new_from_fen
+pub fn is_valid_fen(fen:&str) -> bool {
+    let fields:Vec<&str> = fen.split_whitespace().collect();
+    if fields.len() < 2 {
+        return false
+    }
+
+    let ranks:Vec<&str> = fields[0].split('/').collect();
+    if ranks.len() != 8 {
+        return false
+    }
+    for rank in ranks {
+        let mut files = 0;
+        for c in rank.chars() {
+            match c {
+                '1'..='8' => files += c as usize - '0' as usize,
+                'P' | 'N' | 'B' | 'R' | 'Q' | 'K' | 'p' | 'n' | 'b' | 'r' | 'q' | 'k' => files += 1,
+                _ => return false,
+            }
+        }
+        if files != 8 {
+            return false
+        }
+    }
+    if fields[0].matches('K').count() != 1 || fields[0].matches('k').count() != 1 {
+        return false
+    }
+
+    if fields[1] != "w" && fields[1] != "b" {
+        return false
+    }
+    if let Some(castle) = fields.get(2) {
+        if !castle.chars().all(|c| "KQkq-".contains(c)) {
+            return false
+        }
+    }
+    if let Some(en_passant) = fields.get(3) {
+        let square = en_passant.as_bytes();
+        if *en_passant != "-" && !(square.len() == 2 && (b'a'..=b'h').contains(&square[0]) && (square[1] == b'3' || square[1] == b'6')) {
+            return false
+        }
+    }
+
+    true
+}
+
+// Centipawn or pawn evaluations, with an optional "+", mate scores are rejected
+pub fn parse_evaluation(evaluation:&str) -> Option<f64> {
+    let evaluation = evaluation.trim();
+    evaluation.strip_prefix('+').unwrap_or(evaluation).parse::<f64>().ok().filter(|value| value.is_finite())
+}
+
+// FEN and evaluation columns named in the header, the first two by default
+pub fn find_columns(headers:&StringRecord) -> (usize, usize) {
+    let column = |name:&str, default:usize| headers.iter().position(|header| header.trim().eq_ignore_ascii_case(name)).unwrap_or(default);
+    (column("fen", 0), column("evaluation", 1))
+}
+
+// Converts rows into the output bytes, returns them with the number of skipped rows
+fn convert_rows(rows:&[(String, String)], format:OutputFormat) -> (Vec<u8>, usize) {
+    let mut output = Vec::new();
+    let mut skipped = 0;
+
+    for (fen, evaluation) in rows {
+        let (fen, evaluation) = match parse_evaluation(evaluation) {
+            Some(evaluation) if is_valid_fen(fen) => (fen, evaluation),
+            _ => { skipped += 1; continue; }
+        };
+        let board = Board::new_from_fen(fen.trim());
+
+        match format {
+            OutputFormat::Csv => {
+                for feature in convert_board_to_csv(&board) {
+                    output.extend_from_slice(feature.to_string().as_bytes());
+                    output.push(b',');
+                }
+                output.extend_from_slice(evaluation.to_string().as_bytes());
+                output.push(b'\n');
+            },
+            OutputFormat::Binary => match pack_board(&board, evaluation as f32) {
+                Some(record) => output.extend_from_slice(&record),
+                None => skipped += 1,
+            },
+        }
+    }
+
+    (output, skipped)
+}
+
+pub fn process_csv(input_file: &str, output_file: &str, format: OutputFormat, threads: usize) -> Result<(), Box<dyn Error>> {
+    let threads = threads.max(1);
+
     let mut reader = ReaderBuilder::new()
-        .has_headers(true) // Expect the first row to have column headers
-        .from_path(input_file)?;
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(open_input(input_file)?);
+    let (fen_column, evaluation_column) = find_columns(reader.headers()?);
 
-    // Open the output CSV file for writing
-    let mut writer = WriterBuilder::new()
-        .has_headers(false)
-        .from_path(output_file)?;
+    let mut writer = BufWriter::new(File::create(output_file)?);
+    if format == OutputFormat::Binary {
+        writer.write_all(PACKED_MAGIC)?;
+        writer.write_all(&PACKED_VERSION.to_le_bytes())?;
+    }
 
-    // Write the header row for the output file
-    // writer.write_record(&["FEN", "Evaluation Adjusted"])?;
+    let start = Instant::now();
+    let (mut written, mut skipped, mut next_progress) = (0, 0, PROGRESS_INTERVAL);
+    let mut records = reader.into_records();
+    let mut batch:Vec<(String, String)> = Vec::with_capacity(threads * BATCH_ROWS_PER_THREAD);
 
-    // Process each record in the input file
-    for result in reader.deserialize() {
-        let record: InputRow = result?;
-        
-        // Generate the 773-length feature vector for the FEN
-        let board = Board::new_from_fen(&record.fen);
-        let feature_vector = convert_board_to_csv(&board);
+    loop {
+        // Read the next batch, malformed records are skipped
+        batch.clear();
+        for record in records.by_ref() {
+            match record {
+                Ok(record) => match (record.get(fen_column), record.get(evaluation_column)) {
+                    (Some(fen), Some(evaluation)) => batch.push((fen.to_string(), evaluation.to_string())),
+                    _ => skipped += 1,
+                },
+                Err(e) if e.is_io_error() => return Err(e.into()),
+                Err(_) => skipped += 1,
+            }
+            if batch.len() == batch.capacity() { break; }
+        }
+        if batch.is_empty() { break; }
 
-        // Combine the vector with the evaluation value
-        let mut output_row: Vec<String> = feature_vector.iter().map(|v| v.to_string()).collect();
-        output_row.push(record.evaluation.to_string());
+        // Convert the chunks in parallel, write them back in order
+        let chunk_size = batch.len().div_ceil(threads);
+        let converted:Vec<(Vec<u8>, usize)> = thread::scope(|scope| {
+            let workers:Vec<_> = batch.chunks(chunk_size).map(|chunk| scope.spawn(move || convert_rows(chunk, format))).collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        written += batch.len();
+        for (output, chunk_skipped) in converted {
+            writer.write_all(&output)?;
+            skipped += chunk_skipped;
+            written -= chunk_skipped;
+        }
 
-        // Write the row to the output file
-        writer.write_record(output_row)?;
+        let rows = written + skipped;
+        if rows >= next_progress {
+            println!("Processed {} rows, {} skipped ({:.0} rows/s)", rows, skipped, rows as f64 / start.elapsed().as_secs_f64());
+            next_progress += PROGRESS_INTERVAL;
+        }
     }
 
-    println!("CSV processing complete. Output saved to {}", output_file);
+    writer.flush()?;
+    println!("Processing complete: {} positions written, {} rows skipped in {:.1}s. Output saved to {}", written, skipped, start.elapsed().as_secs_f64(), output_file);
     Ok(())
 }
 
 
+// Uniformly chosen legal move, None when the game is over
+pub unsafe fn random_legal_move(board: &Board, rng: &mut impl Rng) -> Option<usize> {
+    let moves = generate_legal_moves(board);
+    if moves.count == 0 { return None; }
+    Some(moves.moves[rng.gen_range(0..moves.count)])
+}
+
 pub unsafe fn play_random_game(max_turns: usize) {
     let mut board = Board::new_from_fen(START_POSITION);
     let mut rng = rand::thread_rng();
     let mut command = String::from("position startpos moves ");
 
     for _ in 0..max_turns {
-        let moves = generate_moves(&board);
-        if moves.count == 0 { println!("Finished"); return; }
-        let idx = rng.gen_range(0..moves.count);
-        if !board.make_move(moves.moves[idx], false) {
-            continue;
-        }
-        command += &format!("{} ", move_str(moves.moves[idx]));
+        let Some(move_) = random_legal_move(&board, &mut rng) else { println!("Finished"); return; };
+        board.make_legal_move(move_);
+        command += &format!("{} ", move_str(move_));
         println!("{}", command);
     }
 }
@@ -7,14 +7,44 @@ use std::fs::File;
 use std::io::BufWriter;
 use std::str::FromStr;
 use ndarray::Array2;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 
+use crate::_csv_fen_to_bitboard::{convert_board_to_csv, is_packed_file, read_packed, FEATURE_COUNT, PIECE_FEATURES};
+
+
+// Version 1 models (no "version" field) only cover the piece features,
+// version 2 adds side to move, castling rights and the en passant file
+pub const LINEAR_MODEL_VERSION: u32 = 2;
+
+fn legacy_version() -> u32 { 1 }
 
 #[derive(Serialize, Deserialize)]
 pub struct LinearModel {
+    #[serde(default = "legacy_version")]
+    pub version: u32,
     pub coefficients: Vec<f64>,
     pub intercept: f64,
 }
 
+impl LinearModel {
+    pub fn load(path: &str) -> Result<LinearModel, Box<dyn std::error::Error>> {
+        let json_data = std::fs::read_to_string(path)?;
+        let model: LinearModel = serde_json::from_str(&json_data)?;
+
+        let expected = match model.version {
+            1 => PIECE_FEATURES,
+            2 => FEATURE_COUNT,
+            version => return Err(format!("unsupported linear model version {}", version).into()),
+        };
+        if model.coefficients.len() != expected {
+            return Err(format!("version {} linear model needs {} coefficients, found {}", model.version, expected, model.coefficients.len()).into());
+        }
+
+        Ok(model)
+    }
+}
+
+// Older models have fewer coefficients than features, the extra features are ignored
 pub fn predict(model: &LinearModel, inputs: &[f64]) -> f64 {
     let mut prediction = model.intercept;
     for (coef, &input) in model.coefficients.iter().zip(inputs.iter()) {
@@ -58,5 +88,258 @@ pub fn parse_csv<N, R>(input: R) -> Result<DMatrix<N>, Box<dyn std::error::Error
 }
 
 
+/******************************************\
+ ==========================================
+                 Training
+ ==========================================
+\******************************************/
+
+/*
+    Fits a LinearModel on the CSV written by process-csv (features then the score).
+    Every loss is solved through weighted normal equations over the features plus
+    an intercept:
+
+        squared     ordinary least squares, ridge when the L2 penalty is not zero
+        huber       iteratively reweighted least squares, residuals above delta
+                    (in pawns) get weight delta / |residual|
+        logistic    squared error between sigmoid(score / scale) and
+                    sigmoid(prediction / scale), minimized with Gauss-Newton steps
+
+    Squared loss also gives the starting point of the iterative losses.
+*/
+
+#[derive(Clone, Copy)]
+pub enum Loss {
+    Squared,
+    Huber(f64),
+    Logistic(f64),
+}
+
+pub struct TrainOptions {
+    pub loss: Loss,
+    pub ridge: f64,
+    pub validation: f64,
+    pub iterations: usize,
+    pub target_scale: f64,
+}
+
+// Feature rows stored sparsely, most features of a position are zero
+pub struct Dataset {
+    pub n_features: usize,
+    offsets: Vec<usize>,
+    indices: Vec<u16>,
+    values: Vec<u8>,
+    pub targets: Vec<f64>,
+}
 
+impl Dataset {
+    pub fn new(n_features: usize) -> Dataset {
+        Dataset { n_features, offsets: vec![0], indices: Vec::new(), values: Vec::new(), targets: Vec::new() }
+    }
 
+    pub fn push(&mut self, features: &[u8], target: f64) {
+        for (i, &value) in features.iter().enumerate() {
+            if value != 0 {
+                self.indices.push(i as u16);
+                self.values.push(value);
+            }
+        }
+        self.targets.push(target);
+        self.offsets.push(self.indices.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn row(&self, row: usize) -> impl Iterator<Item = (usize, f64)> + '_ {
+        let range = self.offsets[row]..self.offsets[row + 1];
+        self.indices[range.clone()].iter().zip(&self.values[range]).map(|(&i, &v)| (i as usize, v as f64))
+    }
+
+    // Weights hold the coefficients followed by the intercept
+    pub fn predict(&self, weights: &[f64], row: usize) -> f64 {
+        self.row(row).fold(weights[self.n_features], |sum, (i, v)| sum + weights[i] * v)
+    }
+
+    // Shuffled row indices split into (validation, train)
+    pub fn split(&self, validation: f64) -> (Vec<usize>, Vec<usize>) {
+        let mut rows: Vec<usize> = (0..self.len()).collect();
+        rows.shuffle(&mut StdRng::seed_from_u64(0));
+        let n_validation = ((self.len() as f64) * validation.clamp(0.0, 1.0)) as usize;
+        let train = rows.split_off(n_validation);
+        (rows, train)
+    }
+}
+
+// Features and scores from process-csv, in CSV or binary format
+fn read_dataset(path: &str, target_scale: f64) -> Result<Dataset, Box<dyn std::error::Error>> {
+    if is_packed_file(path)? {
+        let mut dataset = Dataset::new(FEATURE_COUNT);
+        read_packed(path, |board, evaluation| dataset.push(&convert_board_to_csv(board), evaluation as f64 * target_scale))?;
+        if dataset.targets.is_empty() {
+            return Err("no positions in the dataset".into())
+        }
+        return Ok(dataset)
+    }
+
+    let reader = BufReader::new(File::open(path)?);
+    let mut dataset = Dataset::new(0);
+    let mut features = Vec::with_capacity(FEATURE_COUNT);
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() { continue; }
+
+        let columns: Vec<&str> = line.trim().split(',').collect();
+        let n_features = columns.len() - 1;
+        if dataset.targets.is_empty() {
+            if n_features != PIECE_FEATURES && n_features != FEATURE_COUNT {
+                return Err(format!("{} features per row, expected {} or {}", n_features, PIECE_FEATURES, FEATURE_COUNT).into())
+            }
+            dataset.n_features = n_features;
+        } else if n_features != dataset.n_features {
+            return Err(format!("line {} has {} features instead of {}", line_number + 1, n_features, dataset.n_features).into())
+        }
+
+        features.clear();
+        for column in columns[..n_features].iter() {
+            features.push(column.trim().parse::<u8>().map_err(|_| format!("line {}: bad feature \"{}\"", line_number + 1, column))?);
+        }
+        let target: f64 = columns[n_features].trim().parse().map_err(|_| format!("line {}: bad score \"{}\"", line_number + 1, columns[n_features]))?;
+        dataset.push(&features, target * target_scale);
+    }
+
+    if dataset.targets.is_empty() {
+        return Err("no positions in the dataset".into())
+    }
+
+    Ok(dataset)
+}
+
+#[inline(always)]
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/*
+    Minimizes sum(h * (x.w - z)^2) / rows + ridge * |coefficients|^2, where every
+    row gives its weight h and target z from its current prediction.
+*/
+fn solve_weighted(dataset: &Dataset, rows: &[usize], ridge: f64, weights: &[f64], row_terms: impl Fn(usize, f64) -> (f64, f64)) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let n = dataset.n_features + 1;
+    let mut a = vec![0.0; n * n];
+    let mut b = vec![0.0; n];
+    let mut active: Vec<(usize, f64)> = Vec::with_capacity(64);
+
+    for &row in rows {
+        let (h, z) = row_terms(row, dataset.predict(weights, row));
+        if h == 0.0 { continue; }
+
+        active.clear();
+        active.extend(dataset.row(row));
+        active.push((dataset.n_features, 1.0));
+
+        for &(i, xi) in active.iter() {
+            b[i] += h * z * xi;
+            for &(j, xj) in active.iter() {
+                a[i * n + j] += h * xi * xj;
+            }
+        }
+    }
+
+    let rows_count = rows.len() as f64;
+    let mut a = DMatrix::from_row_slice(n, n, &a) / rows_count;
+    let b = DVector::from_vec(b) / rows_count;
+    for i in 0..dataset.n_features {
+        a[(i, i)] += ridge;
+    }
+
+    // Piece counts are sums of piece-square bits, without ridge the system is singular
+    let solution = match a.clone().cholesky() {
+        Some(cholesky) => cholesky.solve(&b),
+        None => a.svd(true, true).solve(&b, 1e-10)?,
+    };
+
+    Ok(solution.iter().copied().collect())
+}
+
+fn mean_errors(dataset: &Dataset, rows: &[usize], weights: &[f64], loss: Loss) -> (f64, f64, f64) {
+    let (mut squared, mut absolute, mut sigmoid_squared) = (0.0, 0.0, 0.0);
+    let scale = match loss { Loss::Logistic(scale) => scale, _ => 4.0 };
+
+    for &row in rows {
+        let prediction = dataset.predict(weights, row);
+        let error = dataset.targets[row] - prediction;
+        squared += error * error;
+        absolute += error.abs();
+        let sigmoid_error = sigmoid(dataset.targets[row] / scale) - sigmoid(prediction / scale);
+        sigmoid_squared += sigmoid_error * sigmoid_error;
+    }
+
+    let count = rows.len().max(1) as f64;
+    ((squared / count).sqrt(), absolute / count, sigmoid_squared / count)
+}
+
+fn report(name: &str, dataset: &Dataset, rows: &[usize], weights: &[f64], loss: Loss) {
+    if rows.is_empty() { return; }
+    let (rmse, mae, sigmoid_mse) = mean_errors(dataset, rows, weights, loss);
+    println!("{:<11} rmse {:.4}  mae {:.4}  sigmoid mse {:.6}  (pawns, {} positions)", name, rmse, mae, sigmoid_mse, rows.len());
+}
+
+pub fn train_linear(input: &str, output: &str, options: &TrainOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let dataset = read_dataset(input, options.target_scale)?;
+
+    let (validation, train) = dataset.split(options.validation);
+    let (validation, train) = (&validation[..], &train[..]);
+    if train.is_empty() {
+        return Err("no training positions left after the validation split".into())
+    }
+
+    println!("Loaded {} positions with {} features ({} train, {} validation)", dataset.len(), dataset.n_features, train.len(), validation.len());
+
+    let zero = vec![0.0; dataset.n_features + 1];
+    let mut weights = solve_weighted(&dataset, train, options.ridge, &zero, |row, _| (1.0, dataset.targets[row]))?;
+
+    match options.loss {
+        Loss::Squared => (),
+        Loss::Huber(delta) => {
+            for iteration in 0..options.iterations {
+                weights = solve_weighted(&dataset, train, options.ridge, &weights, |row, prediction| {
+                    let residual = (dataset.targets[row] - prediction).abs();
+                    (if residual <= delta {1.0} else {delta / residual}, dataset.targets[row])
+                })?;
+                let (rmse, mae, _) = mean_errors(&dataset, train, &weights, options.loss);
+                println!("iteration {:>3}: train rmse {:.4} mae {:.4}", iteration + 1, rmse, mae);
+            }
+        },
+        Loss::Logistic(scale) => {
+            for iteration in 0..options.iterations {
+                weights = solve_weighted(&dataset, train, options.ridge, &weights, |row, prediction| {
+                    let predicted = sigmoid(prediction / scale);
+                    let gradient = predicted * (1.0 - predicted) / scale;
+                    if gradient < 1e-12 { return (0.0, 0.0) }
+                    let residual = sigmoid(dataset.targets[row] / scale) - predicted;
+                    (gradient * gradient, prediction + residual / gradient)
+                })?;
+                let (_, _, sigmoid_mse) = mean_errors(&dataset, train, &weights, options.loss);
+                println!("iteration {:>3}: train sigmoid mse {:.6}", iteration + 1, sigmoid_mse);
+            }
+        },
+    }
+
+    report("Train", &dataset, train, &weights, options.loss);
+    report("Validation", &dataset, validation, &weights, options.loss);
+
+    let model = LinearModel {
+        version: if dataset.n_features == FEATURE_COUNT {LINEAR_MODEL_VERSION} else {1},
+        coefficients: weights[..dataset.n_features].to_vec(),
+        intercept: weights[dataset.n_features],
+    };
+    let mut writer = BufWriter::new(File::create(output)?);
+    writer.write_all(serde_json::to_string_pretty(&model)?.as_bytes())?;
+    writer.flush()?;
+
+    println!("Model written to {}", output);
+    Ok(())
+}
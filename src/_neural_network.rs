@@ -1,180 +1,181 @@
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
-use std::io::{self, BufRead, BufReader, Read, Write};
-use std::net::TcpStream;
-use regex::Regex;
-
 use serde::Deserialize;
-use ndarray::{Array2, Array1};
+use ndarray::{Array2, Array1, ArrayView1};
+use std::error::Error;
 use std::fs;
+use std::ptr::addr_of;
+
+use crate::_csv_fen_to_bitboard::{FEATURE_COUNT, PIECE_FEATURES};
+
+/*
+    Multilayer perceptron over the board features, read from a JSON file (EvalFile).
+
+    The file is either a list of layers (hidden layers use ReLU, the output is in pawns)
+    or an object {"layers": [...], "sigmoid_scale": 400}. Every layer has "weights"
+    (one row per input, one column per output), "biases" and an optional "activation":
+    "relu", "clipped_relu" (clamped to [0, 1]), "sigmoid" or "linear". A sigmoid output
+    is a win probability, turned back into centipawns with sigmoid_scale.
+*/
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    Relu,
+    ClippedRelu,
+    Sigmoid,
+    Linear,
+}
 
-// Define the structure for JSON deserialization
+impl Activation {
+    #[inline(always)]
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            Activation::Relu => x.max(0.0),
+            Activation::ClippedRelu => x.clamp(0.0, 1.0),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Linear => x,
+        }
+    }
+}
+
+// Layer as stored in the JSON file
 #[derive(Deserialize)]
-pub struct Layer {
+struct LayerFile {
     weights: Vec<Vec<f64>>, // 2D matrix
     biases: Vec<f64>,       // 1D array
+    #[serde(default)]
+    activation: Option<Activation>,
 }
 
-pub fn relu(x: &Array1<f64>) -> Array1<f64> {
-    x.mapv(|v| if v > 0.0 { v } else { 0.0 })
-}
+fn default_sigmoid_scale() -> f64 { 400.0 }
 
-pub fn predict(input: Array1<f64>, layers: &Vec<Layer>) -> Array1<f64> {
-    let mut activations = input;
-    for (i, layer) in layers.iter().enumerate() {
-        let weights = Array2::from_shape_vec(
-            (layer.weights.len(), layer.weights[0].len()),
-            layer.weights.clone().into_iter().flatten().collect(),
-        )
-        .unwrap();
-        let biases = Array1::from_vec(layer.biases.clone());
-
-        // Perform matrix multiplication and add biases
-        activations = activations.dot(&weights) + biases;
-
-        // Apply ReLU activation for hidden layers
-        if i < layers.len() - 1 {
-            activations = relu(&activations);
-        }
-    }
-    activations // Return the final layer's output
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NetworkFile {
+    Layers(Vec<LayerFile>),
+    Network {
+        layers: Vec<LayerFile>,
+        #[serde(default = "default_sigmoid_scale")]
+        sigmoid_scale: f64,
+    },
 }
 
-pub fn load() -> Vec<Layer> {
-    // Load JSON file
-    let path = "C:/Users/adtro/Uni/MatCAD/3r/APC/kaggle/model_weights.json";
-    let file_content = fs::read_to_string(path).expect("Failed to read file");
-    let layers: Vec<Layer> = serde_json::from_str(&file_content).expect("Failed to parse JSON");
-    layers
+pub struct Layer {
+    weights: Array2<f64>, // inputs x outputs
+    biases: Array1<f64>,
+    activation: Activation,
 }
 
+pub struct Network {
+    layers: Vec<Layer>,
+    sigmoid_scale: f64,
+}
 
-pub fn communicate(server_address: &str, inputs: &Vec<u8>) -> f32 {
-    let mut stream = TcpStream::connect(server_address).unwrap();
-
-    // Prepare JSON payload
-    let input_data = serde_json::json!({
-        "input": inputs,  // Example input data
-    });
+impl Network {
+    pub fn load(path: &str) -> Result<Network, Box<dyn Error>> {
+        let file_content = fs::read_to_string(path)?;
+        let (layer_files, sigmoid_scale) = match serde_json::from_str(&file_content)? {
+            NetworkFile::Layers(layers) => (layers, default_sigmoid_scale()),
+            NetworkFile::Network { layers, sigmoid_scale } => (layers, sigmoid_scale),
+        };
 
-    let payload = input_data.to_string();
+        if layer_files.is_empty() {
+            return Err("network has no layers".into())
+        }
 
-    // Create an HTTP POST request
-    let request = format!(
-        "POST /predict HTTP/1.1\r\n\
-        Host: {}\r\n\
-        Content-Type: application/json\r\n\
-        Content-Length: {}\r\n\r\n\
-        {}",
-        server_address, payload.len(), payload
-    );
+        let mut layers: Vec<Layer> = Vec::with_capacity(layer_files.len());
+        let n_layers = layer_files.len();
+        for (i, layer) in layer_files.into_iter().enumerate() {
+            let inputs = layer.weights.len();
+            let outputs = layer.weights.first().map_or(0, |row| row.len());
+
+            if inputs == 0 || outputs == 0 {
+                return Err(format!("layer {} has an empty weight matrix", i).into())
+            }
+            if layer.weights.iter().any(|row| row.len() != outputs) {
+                return Err(format!("layer {} weight rows have different lengths", i).into())
+            }
+            if layer.biases.len() != outputs {
+                return Err(format!("layer {} has {} outputs but {} biases", i, outputs, layer.biases.len()).into())
+            }
+            match layers.last() {
+                None if inputs != PIECE_FEATURES && inputs != FEATURE_COUNT => {
+                    return Err(format!("first layer takes {} inputs, expected {} or {} features", inputs, PIECE_FEATURES, FEATURE_COUNT).into())
+                },
+                Some(previous) if inputs != previous.biases.len() => {
+                    return Err(format!("layer {} takes {} inputs but the previous layer has {} outputs", i, inputs, previous.biases.len()).into())
+                },
+                _ => (),
+            }
+            if i == n_layers - 1 && outputs != 1 {
+                return Err(format!("output layer has {} outputs, expected 1", outputs).into())
+            }
+
+            let default_activation = if i == n_layers - 1 {Activation::Linear} else {Activation::Relu};
+            layers.push(Layer {
+                weights: Array2::from_shape_vec((inputs, outputs), layer.weights.into_iter().flatten().collect())?,
+                biases: Array1::from_vec(layer.biases),
+                activation: layer.activation.unwrap_or(default_activation),
+            });
+        }
 
-    // Send the request
-    if let Err(e) = stream.write_all(request.as_bytes()) {
-        eprintln!("Failed to send request: {}", e);
-        return 0.0;
+        Ok(Network { layers, sigmoid_scale })
     }
 
-    // Read the response
-    let mut buffer = String::new();
-    if let Err(e) = stream.read_to_string(&mut buffer) {
-        eprintln!("Failed to read response: {}", e);
-        return 0.0;
+    pub fn input_size(&self) -> usize {
+        self.layers[0].weights.nrows()
     }
-    // Blocking on reading the response
-    // let mut buffer = [0; 512];  // Buffer to hold the response
-    // let bytes_read = stream.read(&mut buffer).unwrap();  // Blocks until data is read
-
-    // Regular expression to extract value within the double square brackets [[value]]
-    let re = Regex::new(r"\[\s*\[\s*([\d\.]+)\s*\]\s*\]").unwrap();
-
-    // Search for matches
-    // let response = String::from_utf8_lossy(&buffer[..bytes_read]);
-    let captures = re.captures(&buffer);
-    if let None = captures {
-        return 0.0;
+
+    // Raw network output, inputs are mostly zeros so only the rows of active inputs are added
+    pub fn predict(&self, input: &[f64]) -> f64 {
+        let mut activations = Array1::from_vec(input.to_vec());
+        for layer in self.layers.iter() {
+            activations = dense(layer, activations.view());
+        }
+        activations[0]
     }
-    let value = captures.unwrap().get(1).unwrap().as_str().parse::<f32>().unwrap();
-    // if let Some(captures) = re.captures(response) {
-    //     if let Some(value) = captures.get(1) {
-    //         println!("Extracted value: {}", value.as_str());
-    //     } else {
-    //         println!("No value found in parentheses.");
-    //     }
-    // } else {
-    //     println!("No match found.");
-    // }
-
-    // Print the response
-    value
-}
 
+    // One position per row of inputs
+    pub fn predict_batch(&self, inputs: Array2<f64>) -> Array1<f64> {
+        let mut activations = inputs;
+        for layer in self.layers.iter() {
+            activations = activations.dot(&layer.weights) + &layer.biases;
+            activations.mapv_inplace(|x| layer.activation.apply(x));
+        }
+        activations.column(0).to_owned()
+    }
 
-pub fn open_program() -> (Child, ChildStdin, BufReader<ChildStdout>) {
-    // Define the Python executable and script path
-    let python_executable = "python";
-    let python_script = "C:/Users/adtro/Uni/MatCAD/3r/APC/kaggle/predict.py"; // Ensure this script is in your working directory
-    let model_path = "C:/Users/adtro/Uni/MatCAD/3r/APC/kaggle/my_model.h5";
-
-    // Start the external program
-    let mut child = Command::new(python_executable)
-        .arg(&format!("{}", python_script)) // Path to the Python script
-        .arg(&format!("{}", model_path)) // Path to the nn model
-        .stdin(Stdio::piped()) // Pipe stdin to send input
-        .stdout(Stdio::piped()) // Pipe stdout to read output
-        .spawn()
-        .expect("Failed to start the external program");
-
-    // Get handles to stdin and stdout
-    let mut child_stdin = child.stdin.take().expect("Failed to open stdin");
-    let child_stdout = child.stdout.take().expect("Failed to open stdout");
-
-    // Wrap stdout in a buffered reader for line-by-line reading
-    let mut reader = BufReader::new(child_stdout);
-
-    // Process each line as it's printed
-    for line in reader.by_ref().lines() {
-        match line {
-            Ok(content) => {
-                // println!("--------------------{}", content);
-                if content.trim() == "isready" { break; }
+    // Network output in centipawns for White
+    pub fn centipawns(&self, output: f64) -> i32 {
+        match self.layers[self.layers.len() - 1].activation {
+            Activation::Sigmoid => {
+                let probability = output.clamp(1e-6, 1.0 - 1e-6);
+                (self.sigmoid_scale * (probability / (1.0 - probability)).ln()) as i32
             },
-            Err(e) => { eprintln!("Error reading line: {}", e); break; },
+            _ => (output * 100.0) as i32,
         }
-    };
-    println!("<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<");
-    let inputs = vec![0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,0,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1];
-    send_and_receive(&mut child, &mut child_stdin, &mut reader, inputs.clone());
-    send_and_receive(&mut child, &mut child_stdin, &mut reader, inputs.clone());
-    send_and_receive(&mut child, &mut child_stdin, &mut reader, inputs.clone());
-    send_and_receive(&mut child, &mut child_stdin, &mut reader, inputs);
-    (child, child_stdin, reader)
+    }
 }
 
-pub fn send_and_receive(child: &mut Child, child_stdin: &mut ChildStdin, reader: &mut BufReader<ChildStdout>, inputs: Vec<isize>) {
-    // let mut child_stdin = child.stdin.take().expect("Failed to open stdin");
-    // let child_stdout = child.stdout.take().expect("Failed to open stdout");
-    // // Wrap stdout in a buffered reader for line-by-line reading
-    // let mut reader = BufReader::new(child_stdout);
-
-    let inputs: Vec<String> = inputs.iter().map(|v| v.to_string()).collect();
-    // println!("{}", inputs.join(" "));
-    // Send input to the external program
-    writeln!(child_stdin, "{}", inputs.join(" ")).expect("Failed to write to stdin");
-    child_stdin.flush().expect("Failed to flush stdin");
-
-    // Read and print the response
-    let mut response = String::new();
-    reader.read_line(&mut response).expect("Failed to read from stdout");
-    println!("Response: {}", response.trim());
-    {
-        child
-    };
+#[inline(always)]
+fn dense(layer: &Layer, input: ArrayView1<f64>) -> Array1<f64> {
+    let mut output = layer.biases.clone();
+    for (i, &x) in input.iter().enumerate() {
+        if x != 0.0 {
+            output.scaled_add(x, &layer.weights.row(i));
+        }
+    }
+    output.mapv_inplace(|x| layer.activation.apply(x));
+    output
 }
 
-pub fn close_connection(child_stdin: ChildStdin, mut child: Child) {
-    // Close stdin to signal the end of input
-    drop(child_stdin);
+// Network given by EvalFile, used by the neural evaluator
+static mut EVAL_NETWORK: Option<Network> = None;
+
+pub unsafe fn load_eval_file(path: &str) -> Result<(), Box<dyn Error>> {
+    EVAL_NETWORK = Some(Network::load(path)?);
+    Ok(())
+}
 
-    // Wait for the program to finish
-    let _ = child.wait().expect("Failed to wait on child process");
+pub unsafe fn eval_network() -> Option<&'static Network> {
+    (*addr_of!(EVAL_NETWORK)).as_ref()
 }
@@ -180,6 +180,10 @@ pub static mut ROOK_MASKS:[u64;64] = [0;64];
 pub static mut BISHOP_ATTACKS:[[u64;512];64] = [[0;512];64]; 
 pub static mut ROOK_ATTACKS:[[u64;4096];64] = [[0;4096];64];
 
+// Squares strictly between two aligned squares / full line through them (0 if not aligned)
+pub static mut BETWEEN_MASKS:[[u64;64];64] = [[0;64];64];
+pub static mut LINE_MASKS:[[u64;64];64] = [[0;64];64];
+
 #[inline(always)]
 pub fn get_bishop_attacks(square:usize, mut occupancy:u64) -> u64 {
     unsafe { occupancy &= BISHOP_MASKS[square]; }
@@ -452,6 +456,26 @@ pub unsafe fn init_sliders_attacks(is_bishop:bool) {
 }
 
 
+pub unsafe fn init_line_masks() {
+    for source in 0..64usize {
+        for target in 0..64usize {
+            if source == target { continue; }
+
+            let target_bb = 1u64 << target;
+            let source_bb = 1u64 << source;
+
+            if rook_attacks(source, 0) & target_bb != 0 {
+                BETWEEN_MASKS[source][target] = rook_attacks(source, target_bb) & rook_attacks(target, source_bb);
+                LINE_MASKS[source][target] = (rook_attacks(source, 0) & rook_attacks(target, 0)) | source_bb | target_bb;
+            } else if bishop_attacks(source, 0) & target_bb != 0 {
+                BETWEEN_MASKS[source][target] = bishop_attacks(source, target_bb) & bishop_attacks(target, source_bb);
+                LINE_MASKS[source][target] = (bishop_attacks(source, 0) & bishop_attacks(target, 0)) | source_bb | target_bb;
+            }
+        }
+    }
+}
+
+
 pub fn set_occupancy(index:usize, bits_in_mask:usize, mut attack_mask:u64) -> u64 {
     // Counting using the attack mask bits
     let mut occupancy:u64 = 0;
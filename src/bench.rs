@@ -0,0 +1,56 @@
+/******************************************\
+ ==========================================
+                  Bench
+ ==========================================
+\******************************************/
+
+/*
+    Searches a fixed set of positions to a fixed depth, each from a cleared
+    transposition table and without time limit, so the total node count only
+    changes when the search (or the evaluator) does. The node count is the
+    signature to compare between commits, the nps measures speed.
+*/
+
+use std::time::Instant;
+
+use crate::{bitboard::Board, moves::move_str, transposition::{tt, HASH_SIZE, HASH_TABLE}, uci::{iterative_deepening, set_search_limits}};
+
+pub const BENCH_DEPTH:i32 = 8;
+
+pub const BENCH_POSITIONS:[&str;16] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+    "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1",
+    "r1b1kb1r/2pp1ppp/1np1q3/p3P3/2P5/1P6/PB1NQPPP/R3KB1R b KQkq - 0 1",
+    "3r1rk1/p5pp/bpp1pp2/8/q1PP1P2/b3P3/P2NQRPP/1R2B1K1 b - - 6 22",
+    "6k1/6p1/6Pp/ppp5/3pn2P/1P3K2/1PP2P2/3N4 b - - 0 1",
+    "5k2/7R/4P2p/5K2/p1r2P1p/8/8/8 b - - 0 1",
+    "8/8/1p4kp/p1p2p2/P1P2P2/1P3K1P/8/8 w - - 0 1",
+    "8/5k2/8/3K4/8/2Q5/8/8 w - - 0 1",
+    "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+    "6k1/5ppp/8/8/8/8/r4PPP/1R4K1 w - - 0 1",
+];
+
+pub unsafe fn run_bench(depth:i32) {
+    set_search_limits(None, None);
+
+    let start = Instant::now();
+    let mut total_nodes:u64 = 0;
+
+    for (index, fen) in BENCH_POSITIONS.iter().enumerate() {
+        let mut board = Board::new_from_fen(fen);
+        HASH_TABLE = [tt::new();HASH_SIZE];
+        let result = iterative_deepening(&mut board, depth, false);
+        total_nodes += result.nodes as u64;
+        println!("Position {:>2}/{}: {:<6} score {:>6} nodes {:>10}", index + 1, BENCH_POSITIONS.len(), move_str(result.best_move), result.score, result.nodes);
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("\nDepth {}  time {:.0} ms", depth, elapsed * 1000.0);
+    println!("{} nodes {} nps", total_nodes, (total_nodes as f64 / elapsed.max(1e-9)) as u64);
+}
@@ -58,7 +58,37 @@ pub fn get_ls1b_index(bitboard: u64) -> usize {
 use std::collections::HashMap;
 use std::slice::Iter;
 
-use crate::{Side, Color, Square, SQUARE_TO_COORDINATES, get_move_capture, get_move_source, get_move_target, get_move_piece, get_move_promoted, get_move_double, get_move_enpassant, get_move_castling, move_gen::is_square_attacked, hashing::{generate_hash_key, SIDE_KEY, PIECE_KEYS, ENPASSANT_KEYS, CASTLE_KEYS}};
+use crate::{Side, Color, Square, SQUARE_TO_COORDINATES, get_move_capture, get_move_source, get_move_target, get_move_piece, get_move_promoted, get_move_double, get_move_enpassant, get_move_castling, move_gen::{is_square_attacked, generate_legal_moves, in_check}, hashing::{generate_hash_key, SIDE_KEY, PIECE_KEYS, ENPASSANT_KEYS, CASTLE_KEYS}, linear_score::{generate_linear_score, LINEAR_PIECE_SCORES, LINEAR_ENPASSANT_SCORES, LINEAR_CASTLE_SCORES, LINEAR_SIDE_SCORE}, nnue::{Accumulator, refresh_accumulator, accumulator_in_sync, nnue_add_piece, nnue_remove_piece, nnue_move_piece}};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GameResult {
+    Checkmate(Side), // Winner
+    Stalemate,
+    FiftyMoves,
+    Repetition,
+    InsufficientMaterial,
+}
+
+impl GameResult {
+    // 1 White win, 0.5 draw, 0 Black win
+    pub fn white_score(&self) -> f64 {
+        match self {
+            GameResult::Checkmate(Side::White) => 1.0,
+            GameResult::Checkmate(_) => 0.0,
+            _ => 0.5,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            GameResult::Checkmate(_) => "checkmate",
+            GameResult::Stalemate => "stalemate",
+            GameResult::FiftyMoves => "fifty moves",
+            GameResult::Repetition => "repetition",
+            GameResult::InsufficientMaterial => "insufficient material",
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Board {
@@ -70,6 +100,8 @@ pub struct Board {
     pub castle:u8,
 
     pub hash_key:u64,
+    pub linear_score:i32,
+    pub accumulator:Accumulator,
     pub repetition_table:[u64;1000], // Number of PLY in the intire game
     pub repetition_index:usize,
 }
@@ -83,6 +115,8 @@ impl Board {
             en_passant:None,
             castle:0,
             hash_key:0,
+            linear_score:0,
+            accumulator:Accumulator::new(),
             repetition_table:[0;1000],
             repetition_index:0,
         }
@@ -167,161 +201,265 @@ impl Board {
         board.occupancies[Color::Both as usize] |= board.occupancies[Color::White as usize];
         board.occupancies[Color::Both as usize] |= board.occupancies[Color::Black as usize];
 
-        unsafe { board.hash_key = generate_hash_key(&board); }
+        unsafe {
+            board.hash_key = generate_hash_key(&board);
+            board.linear_score = generate_linear_score(&board);
+            refresh_accumulator(&mut board);
+        }
 
         board
     }
 
-    pub unsafe fn make_move(&mut self, move_:usize, only_captures:bool) -> bool {
-        if !only_captures {
-            let previous_board = self.clone();
+    // Halfmove clock and move number are not tracked, they are written as "0 1"
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
 
-            // Parsing move
-            let source_square = get_move_source!(move_);
-            let target_square = get_move_target!(move_);
-            let piece = get_move_piece!(move_);
-            let promoted = get_move_promoted!(move_);
-            let capture = get_move_capture!(move_);
-            let double = get_move_double!(move_);
-            let enpassant = get_move_enpassant!(move_);
-            let castle = get_move_castling!(move_);
-
-            // Updating move
-            pop_bit!(self.bitboards[piece], source_square);
-            set_bit!(self.bitboards[piece], target_square);
-
-            // Hash piece
-            self.hash_key ^= PIECE_KEYS[piece][source_square]; // Remove piece
-            self.hash_key ^= PIECE_KEYS[piece][target_square]; // Set piece
-
-            // If capture, remove bit from opponents bitboard
-            if capture {
-                let offset = if self.side == Side::White {6} else {0};
-                for bb_piece in (0+offset)..(6+offset) {
-                    if get_bit!(self.bitboards[bb_piece], target_square) != 0 {
-                        pop_bit!(self.bitboards[bb_piece], target_square);
-                        // Remove piece from hash key
-                        self.hash_key ^= PIECE_KEYS[bb_piece][target_square];
-                        break;
-                    }
+        for rank in 0..8 {
+            let mut empty = 0;
+            for file in 0..8 {
+                let square = rank*8 + file;
+                match (0..12).find(|&piece| get_bit!(self.bitboards[piece], square) != 0) {
+                    Some(piece) => {
+                        if empty != 0 { fen.push_str(&empty.to_string()); empty = 0; }
+                        fen.push(ASCII_PIECES[piece]);
+                    },
+                    None => empty += 1,
                 }
             }
+            if empty != 0 { fen.push_str(&empty.to_string()); }
+            if rank != 7 { fen.push('/'); }
+        }
 
-            // If promotion, update corresponding bitboard
-            if promoted < 12 {
-                pop_bit!(self.bitboards[piece], target_square);
-                self.hash_key ^= PIECE_KEYS[piece][target_square];
-                set_bit!(self.bitboards[promoted], target_square);
-                self.hash_key ^= PIECE_KEYS[promoted][target_square];
-            }
+        fen.push_str(if self.side == Side::White {" w "} else {" b "});
 
-            // Manage enpassant case
-            if enpassant {
-                if self.side == Side::White {
-                    pop_bit!(self.bitboards[6], target_square+8);
-                    self.hash_key ^= PIECE_KEYS[6][target_square+8];
-                } else {
-                    pop_bit!(self.bitboards[0], target_square-8);
-                    self.hash_key ^= PIECE_KEYS[0][target_square-8];
-                }
-            }
+        if self.castle == 0 {
+            fen.push('-');
+        } else {
+            if self.castle & CastlingSide::WK as u8 != 0 { fen.push('K'); }
+            if self.castle & CastlingSide::WQ as u8 != 0 { fen.push('Q'); }
+            if self.castle & CastlingSide::BK as u8 != 0 { fen.push('k'); }
+            if self.castle & CastlingSide::BQ as u8 != 0 { fen.push('q'); }
+        }
 
-            if let Some(square) = self.en_passant {
-                self.hash_key ^= ENPASSANT_KEYS[square]
-            }
+        match self.en_passant {
+            Some(square) => fen.push_str(&format!(" {} 0 1", SQUARE_TO_COORDINATES[square])),
+            None => fen.push_str(" - 0 1"),
+        }
 
-            self.en_passant = None;
+        fen
+    }
 
-            // Enabling enpassant square /if double push
-            if double {
-                if self.side == Side::White {
-                    self.en_passant = Some(target_square+8);
-                    self.hash_key ^= ENPASSANT_KEYS[target_square+8];
-                } else {
-                    self.en_passant = Some(target_square-8);
-                    self.hash_key ^= ENPASSANT_KEYS[target_square-8];
-                }
-            }
+    // No pawns, rooks or queens and at most one minor piece
+    pub fn insufficient_material(&self) -> bool {
+        let heavy = [Pieces::P, Pieces::R, Pieces::Q, Pieces::p, Pieces::r, Pieces::q];
+        let minor = [Pieces::N, Pieces::B, Pieces::n, Pieces::b];
+        heavy.iter().all(|&piece| self.bitboards[piece as usize] == 0)
+            && minor.iter().map(|&piece| count_bits(self.bitboards[piece as usize])).sum::<usize>() <= 1
+    }
 
-            // Case of castling
-            if castle && self.castle!=0 {
-                match target_square {
-                    x if x == Square::g1 as usize => {
-                        pop_bit!(self.bitboards[Pieces::R as usize], Square::h1 as usize);
-                        set_bit!(self.bitboards[Pieces::R as usize], Square::f1 as usize);
-                        self.hash_key ^= PIECE_KEYS[Pieces::R as usize][Square::h1 as usize];
-                        self.hash_key ^= PIECE_KEYS[Pieces::R as usize][Square::f1 as usize];
-                    },
-                    x if x == Square::c1 as usize => {
-                        pop_bit!(self.bitboards[Pieces::R as usize], Square::a1 as usize);
-                        set_bit!(self.bitboards[Pieces::R as usize], Square::d1 as usize);
-                        self.hash_key ^= PIECE_KEYS[Pieces::R as usize][Square::a1 as usize];
-                        self.hash_key ^= PIECE_KEYS[Pieces::R as usize][Square::d1 as usize];
-                    },
-                    x if x == Square::g8 as usize => {
-                        pop_bit!(self.bitboards[Pieces::r as usize], Square::h8 as usize);
-                        set_bit!(self.bitboards[Pieces::r as usize], Square::f8 as usize);
-                        self.hash_key ^= PIECE_KEYS[Pieces::r as usize][Square::h8 as usize];
-                        self.hash_key ^= PIECE_KEYS[Pieces::r as usize][Square::f8 as usize];
-                    },
-                    x if x == Square::c8 as usize => {
-                        pop_bit!(self.bitboards[Pieces::r as usize], Square::a8 as usize);
-                        set_bit!(self.bitboards[Pieces::r as usize], Square::d8 as usize);
-                        self.hash_key ^= PIECE_KEYS[Pieces::r as usize][Square::a8 as usize];
-                        self.hash_key ^= PIECE_KEYS[Pieces::r as usize][Square::d8 as usize];
-                    },
-                    _ => (),
-                }
-            }
+    /*
+        Result of the game if it is over. history holds the hash key of every position
+        of the game (this one included) and halfmove_clock the plies since the last
+        capture or pawn move.
+    */
+    pub unsafe fn game_result(&self, history:&[u64], halfmove_clock:usize) -> Option<GameResult> {
+        if generate_legal_moves(self).count == 0 {
+            return Some(if !in_check(self) {
+                GameResult::Stalemate
+            } else if self.side == Side::White {
+                GameResult::Checkmate(Side::Black)
+            } else {
+                GameResult::Checkmate(Side::White)
+            })
+        }
+        if halfmove_clock >= 100 {
+            return Some(GameResult::FiftyMoves)
+        }
+        if history.iter().filter(|&&key| key == self.hash_key).count() >= 3 {
+            return Some(GameResult::Repetition)
+        }
+        if self.insufficient_material() {
+            return Some(GameResult::InsufficientMaterial)
+        }
+        None
+    }
 
-            // Updating castle rights
-            if self.castle != 0 {
-                self.hash_key ^= CASTLE_KEYS[self.castle as usize];
-                self.castle &= CASTLING_RIGHTS[source_square];
-                self.castle &= CASTLING_RIGHTS[target_square];
-                self.hash_key ^= CASTLE_KEYS[self.castle as usize];
-            }
+    pub unsafe fn make_move(&mut self, move_:usize, only_captures:bool) -> bool {
+        if !only_captures {
+            let previous_board = self.clone();
+
+            self.make_legal_move(move_);
 
-            // Update occupancies
-            self.occupancies = [0;3];
-            for w_piece in (Pieces::P as usize)..(Pieces::K as usize + 1) {
-                self.occupancies[0] |= self.bitboards[w_piece];
+            // Check if the move is legal
+            let moved_white = self.side == Side::Black;
+            let king_square = get_ls1b_index(self.bitboards[if moved_white {Pieces::K as usize} else {Pieces::k as usize}]);
+            if is_square_attacked(self, king_square, !moved_white) {
+                *self = previous_board;
+                false
+            } else {
+                true
             }
-            for b_piece in (Pieces::p as usize)..(Pieces::k as usize + 1) {
-                self.occupancies[1] |= self.bitboards[b_piece];
+        } else if get_move_capture!(move_) {
+            self.make_move(move_, false)
+        } else {
+            false
+        }
+    }
+
+    // Moves from generate_legal_moves (or parse_move) need neither the copy nor the check test of make_move
+    pub unsafe fn make_legal_move(&mut self, move_:usize) {
+        // Parsing move
+        let source_square = get_move_source!(move_);
+        let target_square = get_move_target!(move_);
+        let piece = get_move_piece!(move_);
+        let promoted = get_move_promoted!(move_);
+        let capture = get_move_capture!(move_);
+        let double = get_move_double!(move_);
+        let enpassant = get_move_enpassant!(move_);
+        let castle = get_move_castling!(move_);
+
+        // Updating move
+        pop_bit!(self.bitboards[piece], source_square);
+        set_bit!(self.bitboards[piece], target_square);
+
+        // Hash piece
+        self.hash_key ^= PIECE_KEYS[piece][source_square]; // Remove piece
+        self.hash_key ^= PIECE_KEYS[piece][target_square]; // Set piece
+        self.linear_score += LINEAR_PIECE_SCORES[piece][target_square] - LINEAR_PIECE_SCORES[piece][source_square];
+        nnue_move_piece(&mut self.accumulator, piece, source_square, target_square);
+
+        // If capture, remove bit from opponents bitboard
+        if capture {
+            let offset = if self.side == Side::White {6} else {0};
+            for bb_piece in (0+offset)..(6+offset) {
+                if get_bit!(self.bitboards[bb_piece], target_square) != 0 {
+                    pop_bit!(self.bitboards[bb_piece], target_square);
+                    // Remove piece from hash key
+                    self.hash_key ^= PIECE_KEYS[bb_piece][target_square];
+                    self.linear_score -= LINEAR_PIECE_SCORES[bb_piece][target_square];
+                    nnue_remove_piece(&mut self.accumulator, bb_piece, target_square);
+                    break;
+                }
             }
-            self.occupancies[Color::Both as usize] |= self.occupancies[Color::White as usize];
-            self.occupancies[Color::Both as usize] |= self.occupancies[Color::Black as usize];
+        }
 
-            // Check if the move is legal
-            let other_side = if self.side==Side::White {false} else {true};
-            self.side = if other_side {Side::White} else {Side::Black};
+        // If promotion, update corresponding bitboard
+        if promoted < 12 {
+            pop_bit!(self.bitboards[piece], target_square);
+            self.hash_key ^= PIECE_KEYS[piece][target_square];
+            set_bit!(self.bitboards[promoted], target_square);
+            self.hash_key ^= PIECE_KEYS[promoted][target_square];
+            self.linear_score += LINEAR_PIECE_SCORES[promoted][target_square] - LINEAR_PIECE_SCORES[piece][target_square];
+            nnue_remove_piece(&mut self.accumulator, piece, target_square);
+            nnue_add_piece(&mut self.accumulator, promoted, target_square);
+        }
 
-            // Hashing key
-            self.hash_key ^= SIDE_KEY;
+        // Manage enpassant case
+        if enpassant {
+            if self.side == Side::White {
+                pop_bit!(self.bitboards[6], target_square+8);
+                self.hash_key ^= PIECE_KEYS[6][target_square+8];
+                self.linear_score -= LINEAR_PIECE_SCORES[6][target_square+8];
+                nnue_remove_piece(&mut self.accumulator, 6, target_square+8);
+            } else {
+                pop_bit!(self.bitboards[0], target_square-8);
+                self.hash_key ^= PIECE_KEYS[0][target_square-8];
+                self.linear_score -= LINEAR_PIECE_SCORES[0][target_square-8];
+                nnue_remove_piece(&mut self.accumulator, 0, target_square-8);
+            }
+        }
 
-            self.repetition_index = 0;
+        if let Some(square) = self.en_passant {
+            self.hash_key ^= ENPASSANT_KEYS[square];
+            self.linear_score -= LINEAR_ENPASSANT_SCORES[square];
+        }
 
+        self.en_passant = None;
 
-            if is_square_attacked(
-                self, 
-                if other_side {get_ls1b_index(self.bitboards[11])} else {get_ls1b_index(self.bitboards[5])}, 
-                other_side
-            ) {
-                *self = previous_board;
-                return false
+        // Enabling enpassant square /if double push
+        if double {
+            if self.side == Side::White {
+                self.en_passant = Some(target_square+8);
+                self.hash_key ^= ENPASSANT_KEYS[target_square+8];
+                self.linear_score += LINEAR_ENPASSANT_SCORES[target_square+8];
             } else {
-                return true
+                self.en_passant = Some(target_square-8);
+                self.hash_key ^= ENPASSANT_KEYS[target_square-8];
+                self.linear_score += LINEAR_ENPASSANT_SCORES[target_square-8];
             }
-            
+        }
 
-        } else {
-            if get_move_capture!(move_) {
-                return self.make_move(move_, false)
-            } else {
-                return false
+        // Case of castling
+        if castle && self.castle!=0 {
+            match target_square {
+                x if x == Square::g1 as usize => {
+                    pop_bit!(self.bitboards[Pieces::R as usize], Square::h1 as usize);
+                    set_bit!(self.bitboards[Pieces::R as usize], Square::f1 as usize);
+                    self.hash_key ^= PIECE_KEYS[Pieces::R as usize][Square::h1 as usize];
+                    self.hash_key ^= PIECE_KEYS[Pieces::R as usize][Square::f1 as usize];
+                    self.linear_score += LINEAR_PIECE_SCORES[Pieces::R as usize][Square::f1 as usize] - LINEAR_PIECE_SCORES[Pieces::R as usize][Square::h1 as usize];
+                    nnue_move_piece(&mut self.accumulator, Pieces::R as usize, Square::h1 as usize, Square::f1 as usize);
+                },
+                x if x == Square::c1 as usize => {
+                    pop_bit!(self.bitboards[Pieces::R as usize], Square::a1 as usize);
+                    set_bit!(self.bitboards[Pieces::R as usize], Square::d1 as usize);
+                    self.hash_key ^= PIECE_KEYS[Pieces::R as usize][Square::a1 as usize];
+                    self.hash_key ^= PIECE_KEYS[Pieces::R as usize][Square::d1 as usize];
+                    self.linear_score += LINEAR_PIECE_SCORES[Pieces::R as usize][Square::d1 as usize] - LINEAR_PIECE_SCORES[Pieces::R as usize][Square::a1 as usize];
+                    nnue_move_piece(&mut self.accumulator, Pieces::R as usize, Square::a1 as usize, Square::d1 as usize);
+                },
+                x if x == Square::g8 as usize => {
+                    pop_bit!(self.bitboards[Pieces::r as usize], Square::h8 as usize);
+                    set_bit!(self.bitboards[Pieces::r as usize], Square::f8 as usize);
+                    self.hash_key ^= PIECE_KEYS[Pieces::r as usize][Square::h8 as usize];
+                    self.hash_key ^= PIECE_KEYS[Pieces::r as usize][Square::f8 as usize];
+                    self.linear_score += LINEAR_PIECE_SCORES[Pieces::r as usize][Square::f8 as usize] - LINEAR_PIECE_SCORES[Pieces::r as usize][Square::h8 as usize];
+                    nnue_move_piece(&mut self.accumulator, Pieces::r as usize, Square::h8 as usize, Square::f8 as usize);
+                },
+                x if x == Square::c8 as usize => {
+                    pop_bit!(self.bitboards[Pieces::r as usize], Square::a8 as usize);
+                    set_bit!(self.bitboards[Pieces::r as usize], Square::d8 as usize);
+                    self.hash_key ^= PIECE_KEYS[Pieces::r as usize][Square::a8 as usize];
+                    self.hash_key ^= PIECE_KEYS[Pieces::r as usize][Square::d8 as usize];
+                    self.linear_score += LINEAR_PIECE_SCORES[Pieces::r as usize][Square::d8 as usize] - LINEAR_PIECE_SCORES[Pieces::r as usize][Square::a8 as usize];
+                    nnue_move_piece(&mut self.accumulator, Pieces::r as usize, Square::a8 as usize, Square::d8 as usize);
+                },
+                _ => (),
             }
         }
+
+        // Updating castle rights
+        if self.castle != 0 {
+            self.hash_key ^= CASTLE_KEYS[self.castle as usize];
+            self.linear_score -= LINEAR_CASTLE_SCORES[self.castle as usize];
+            self.castle &= CASTLING_RIGHTS[source_square];
+            self.castle &= CASTLING_RIGHTS[target_square];
+            self.hash_key ^= CASTLE_KEYS[self.castle as usize];
+            self.linear_score += LINEAR_CASTLE_SCORES[self.castle as usize];
+        }
+
+        // Update occupancies
+        self.occupancies = [0;3];
+        for w_piece in (Pieces::P as usize)..(Pieces::K as usize + 1) {
+            self.occupancies[0] |= self.bitboards[w_piece];
+        }
+        for b_piece in (Pieces::p as usize)..(Pieces::k as usize + 1) {
+            self.occupancies[1] |= self.bitboards[b_piece];
+        }
+        self.occupancies[Color::Both as usize] |= self.occupancies[Color::White as usize];
+        self.occupancies[Color::Both as usize] |= self.occupancies[Color::Black as usize];
+
+        let other_side = if self.side==Side::White {false} else {true};
+        self.side = if other_side {Side::White} else {Side::Black};
+
+        // Hashing key
+        self.hash_key ^= SIDE_KEY;
+        self.linear_score += if other_side {LINEAR_SIDE_SCORE} else {-LINEAR_SIDE_SCORE};
+
+        debug_assert_eq!(self.linear_score, generate_linear_score(self), "incremental linear score out of sync");
+        debug_assert!(accumulator_in_sync(self), "incremental NNUE accumulator out of sync");
+
+        self.repetition_index = 0;
     }
 
     pub fn take_back(&mut self, other:&Board) {
@@ -331,6 +469,8 @@ impl Board {
         self.en_passant = other.en_passant;
         self.castle = other.castle;
         self.hash_key = other.hash_key;
+        self.linear_score = other.linear_score;
+        self.accumulator = other.accumulator;
         self.repetition_index = other.repetition_index;
     }
 
@@ -0,0 +1,363 @@
+/******************************************\
+ ==========================================
+             Polyglot Books
+ ==========================================
+\******************************************/
+
+/*
+    Polyglot opening books: 16 byte big-endian entries sorted by key
+        key (u64)  move (u16)  weight (u16)  learn (u32)
+    The key is hashing::polyglot_key. Moves hold the target square in bits 0-5 and the
+    source in bits 6-11 (a1 = 0), the promotion in bits 12-14 (1 knight ... 4 queen),
+    and castling is written as the king taking its own rook (e1h1 for e1g1).
+
+    With OwnBook on, "go" plays a book move when the position is in BookFile: the
+    heaviest one with BookMode best, or one drawn with probability proportional to
+    its weight (weighted).
+
+    The book subcommand builds a book from PGN games: every move of the first plies
+    of each game is counted with the game result, moves played in fewer than
+    min-games games are dropped, and the weight is
+        wins * win-weight + draws * draw-weight + losses * loss-weight
+    for the side that played it, scaled down to fit in 16 bits.
+*/
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ptr::addr_of;
+
+use rand::prelude::*;
+
+use crate::{SQUARE_TO_COORDINATES, Side, START_POSITION, get_move_source, get_move_target, get_move_promoted, get_move_castling};
+use crate::bitboard::Board;
+use crate::hashing::polyglot_key;
+use crate::move_gen::generate_legal_moves;
+use crate::moves::{move_str, parse_san};
+
+pub static mut OWN_BOOK:bool = false;
+pub static mut BOOK_FILE:Option<String> = None;
+pub static mut BOOK:Option<Book> = None;
+pub static mut BOOK_BEST:bool = false;
+
+pub const BOOK_MODES:[&str;2] = ["weighted", "best"];
+
+#[derive(Clone, Copy)]
+pub struct BookEntry {
+    pub key:u64,
+    pub move_:u16,
+    pub weight:u16,
+}
+
+pub struct Book {
+    entries:Vec<BookEntry>,
+}
+
+impl Book {
+    pub fn load(path:&str) -> Result<Book, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+        if bytes.len() % 16 != 0 {
+            return Err(format!("{} is not a Polyglot book ({} bytes)", path, bytes.len()).into())
+        }
+
+        // The learn field (bytes 12..16) is not used
+        let mut entries:Vec<BookEntry> = bytes.chunks_exact(16).map(|entry| BookEntry {
+            key: u64::from_be_bytes(entry[0..8].try_into().unwrap()),
+            move_: u16::from_be_bytes(entry[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(entry[10..12].try_into().unwrap()),
+        }).collect();
+        // Books should be sorted already, lookups rely on it
+        entries.sort_by_key(|entry| entry.key);
+
+        Ok(Book { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn entries(&self, key:u64) -> &[BookEntry] {
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let end = self.entries.partition_point(|entry| entry.key <= key);
+        &self.entries[start..end]
+    }
+}
+
+// Engine square (a8 = 0) to Polyglot square (a1 = 0) and back
+fn polyglot_square(square:usize) -> usize {
+    square ^ 56
+}
+
+// Book move in UCI notation as the engine writes it
+fn polyglot_move_str(board:&Board, move_:u16) -> String {
+    let target = polyglot_square((move_ & 63) as usize);
+    let source = polyglot_square(((move_ >> 6) & 63) as usize);
+    let promoted = (move_ >> 12) & 7;
+
+    // King takes its own rook: castling
+    let king = board.bitboards[if board.side == Side::White {5} else {11}];
+    let target = if king & (1 << source) != 0 && (source == 60 || source == 4) && (target == source + 3 || target == source - 4) {
+        if target > source {source + 2} else {source - 2}
+    } else {
+        target
+    };
+
+    let mut uci = format!("{}{}", SQUARE_TO_COORDINATES[source], SQUARE_TO_COORDINATES[target]);
+    if promoted != 0 {
+        uci.push(['n', 'b', 'r', 'q'][promoted as usize - 1]);
+    }
+    uci
+}
+
+fn encode_polyglot_move(move_:usize) -> u16 {
+    let source = get_move_source!(move_);
+    let mut target = get_move_target!(move_);
+    if get_move_castling!(move_) {
+        // Rook square: h-file for O-O, a-file for O-O-O
+        target = if target % 8 == 6 {target + 1} else {target - 2};
+    }
+
+    let promoted = get_move_promoted!(move_);
+    let promotion = if promoted < 12 {promoted % 6} else {0};
+    (polyglot_square(target) | polyglot_square(source) << 6 | promotion << 12) as u16
+}
+
+// Book move for the position (OwnBook on and a book loaded), legal on the board
+pub unsafe fn book_move(board:&Board) -> Option<usize> {
+    if !OWN_BOOK {
+        return None
+    }
+    let book = (*addr_of!(BOOK)).as_ref()?;
+    let entries:Vec<BookEntry> = book.entries(polyglot_key(board)).iter().copied().filter(|entry| entry.weight > 0).collect();
+    if entries.is_empty() {
+        return None
+    }
+
+    let entry = if BOOK_BEST {
+        entries.iter().max_by_key(|entry| entry.weight)?
+    } else {
+        let total:u32 = entries.iter().map(|entry| entry.weight as u32).sum();
+        let mut pick = thread_rng().gen_range(0..total);
+        entries.iter().find(|entry| {
+            let found = pick < entry.weight as u32;
+            pick = pick.saturating_sub(entry.weight as u32);
+            found
+        })?
+    };
+
+    let wanted = polyglot_move_str(board, entry.move_);
+    let moves = generate_legal_moves(board);
+    moves.moves[..moves.count].iter().copied().find(|&move_| move_str(move_) == wanted)
+}
+
+// BookFile option, "<empty>" clears it
+pub unsafe fn set_book_file(path:&str) {
+    BOOK_FILE = None;
+    BOOK = None;
+
+    if path.is_empty() || path == "<empty>" {
+        return
+    }
+
+    match Book::load(path) {
+        Ok(book) => {
+            println!("info string loaded {} book entries from {}", book.len(), path);
+            BOOK_FILE = Some(path.to_string());
+            BOOK = Some(book);
+        },
+        Err(e) => println!("info string cannot load book {}: {}", path, e),
+    }
+}
+
+pub fn book_mode_name() -> &'static str {
+    unsafe { if BOOK_BEST {"best"} else {"weighted"} }
+}
+
+pub unsafe fn set_book_mode(name:&str) -> Result<(), String> {
+    match name.to_lowercase().as_str() {
+        "weighted" => BOOK_BEST = false,
+        "best" => BOOK_BEST = true,
+        _ => return Err(format!("Unknown book mode {} (expected {})", name, BOOK_MODES.join(" or "))),
+    }
+    Ok(())
+}
+
+/******************************************\
+ ==========================================
+                  PGN
+ ==========================================
+\******************************************/
+
+pub struct PgnGame {
+    pub fen:Option<String>,
+    pub result:Option<f64>, // White's score, None for unfinished games
+    pub moves:Vec<String>,
+}
+
+fn parse_result(text:&str) -> Option<f64> {
+    match text {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" => Some(0.5),
+        _ => None,
+    }
+}
+
+// Move without its move number ("12." or "12..."), castling written with zeros is kept
+fn strip_move_number(token:&str) -> &str {
+    match token.find('.') {
+        Some(dot) if dot > 0 && token[..dot].bytes().all(|c| c.is_ascii_digit()) => token[dot..].trim_start_matches('.'),
+        _ => token,
+    }
+}
+
+// SAN moves of a movetext, without comments, variations, NAGs and move numbers
+fn movetext_moves(text:&str) -> Vec<String> {
+    let mut moves = Vec::new();
+    let mut current = String::new();
+    let mut comment = false;
+    let mut line_comment = false;
+    let mut variation = 0;
+
+    let flush = |current:&mut String, moves:&mut Vec<String>| {
+        let token = if current == "*" || parse_result(current).is_some() {""} else {strip_move_number(current)};
+        if !token.is_empty() && !token.starts_with('$') {
+            moves.push(token.to_string());
+        }
+        current.clear();
+    };
+
+    for c in text.chars() {
+        match c {
+            _ if line_comment => if c == '\n' { line_comment = false },
+            _ if comment => if c == '}' { comment = false },
+            '{' => { flush(&mut current, &mut moves); comment = true },
+            ';' => { flush(&mut current, &mut moves); line_comment = true },
+            '(' => { flush(&mut current, &mut moves); variation += 1 },
+            ')' => { current.clear(); variation -= 1 },
+            _ if variation > 0 => (),
+            _ if c.is_whitespace() => flush(&mut current, &mut moves),
+            _ => current.push(c),
+        }
+    }
+    flush(&mut current, &mut moves);
+
+    moves
+}
+
+pub fn parse_pgn(text:&str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame { fen: None, result: None, moves: Vec::new() };
+    let mut movetext = String::new();
+
+    for line in text.lines().chain(std::iter::once("")) {
+        let line = line.trim();
+        if line.starts_with('[') {
+            // A tag after movetext starts the next game
+            if !movetext.trim().is_empty() {
+                game.moves = movetext_moves(&movetext);
+                games.push(std::mem::replace(&mut game, PgnGame { fen: None, result: None, moves: Vec::new() }));
+                movetext.clear();
+            }
+
+            let tag = line.trim_start_matches('[').trim_end_matches(']');
+            if let Some((name, value)) = tag.split_once(char::is_whitespace) {
+                let value = value.trim().trim_matches('"');
+                match name {
+                    "Result" => game.result = parse_result(value),
+                    "FEN" => game.fen = Some(value.to_string()),
+                    _ => (),
+                }
+            }
+        } else {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+    if !movetext.trim().is_empty() {
+        game.moves = movetext_moves(&movetext);
+        games.push(game);
+    }
+
+    games
+}
+
+pub struct BookOptions {
+    pub max_plies:usize,
+    pub min_games:u32,
+    pub win_weight:u32,
+    pub draw_weight:u32,
+    pub loss_weight:u32,
+}
+
+// book: Polyglot book from a PGN file
+pub unsafe fn build_book(input:&str, output:&str, options:&BookOptions) -> Result<(), Box<dyn Error>> {
+    let games = parse_pgn(&fs::read_to_string(input)?);
+
+    // Games and weight of every (position, move)
+    let mut stats:HashMap<(u64, u16), (u32, u64)> = HashMap::new();
+    let (mut used, mut unfinished, mut illegal) = (0, 0, 0);
+
+    for game in &games {
+        let result = match game.result {
+            Some(result) => result,
+            None => { unfinished += 1; continue; },
+        };
+
+        let mut board = Board::new_from_fen(game.fen.as_deref().unwrap_or(START_POSITION));
+        for san in game.moves.iter().take(options.max_plies) {
+            let move_ = parse_san(&board, san);
+            if move_ == 0 {
+                illegal += 1;
+                break;
+            }
+
+            let score = if board.side == Side::White {result} else {1.0 - result};
+            let weight = if score > 0.75 {options.win_weight} else if score < 0.25 {options.loss_weight} else {options.draw_weight};
+            let entry = stats.entry((polyglot_key(&board), encode_polyglot_move(move_))).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += weight as u64;
+
+            board.make_legal_move(move_);
+        }
+        used += 1;
+    }
+
+    let mut entries:Vec<(u64, u16, u64)> = stats.into_iter()
+        .filter(|&(_, (count, weight))| count >= options.min_games && weight > 0)
+        .map(|((key, move_), (_, weight))| (key, move_, weight))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| b.2.cmp(&a.2)));
+
+    let heaviest = entries.iter().map(|entry| entry.2).max().unwrap_or(0);
+    let mut file = BufWriter::new(File::create(output)?);
+    for &(key, move_, weight) in &entries {
+        let weight = if heaviest > u16::MAX as u64 { (weight * u16::MAX as u64 / heaviest).max(1) } else { weight };
+        file.write_all(&key.to_be_bytes())?;
+        file.write_all(&move_.to_be_bytes())?;
+        file.write_all(&(weight as u16).to_be_bytes())?;
+        file.write_all(&0u32.to_be_bytes())?;
+    }
+    file.flush()?;
+
+    println!("{} games read: {} used, {} without result, {} stopped at an illegal move", games.len(), used, unfinished, illegal);
+    println!("{} book entries written to {}", entries.len(), output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movetext_drops_numbers_comments_and_variations() {
+        let moves = movetext_moves("1. e4 {best by test} e5 2.Nf3 (2. f4 exf4) 2... Nc6 $1 3. Bc4 Bc5 ; Italian\n4. 0-0 Nf6 5. O-O-O 1-0");
+        assert_eq!(moves, ["e4", "e5", "Nf3", "Nc6", "Bc4", "Bc5", "0-0", "Nf6", "O-O-O"]);
+    }
+
+    #[test]
+    fn castling_with_zeros_is_kept() {
+        assert_eq!(movetext_moves("12.0-0 0-0-0 13...0-0"), ["0-0", "0-0-0", "0-0"]);
+    }
+}
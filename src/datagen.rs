@@ -0,0 +1,164 @@
+/******************************************\
+ ==========================================
+            Self-play Data Generation
+ ==========================================
+\******************************************/
+
+/*
+    Games of the engine against itself, each starting with a few random legal moves so
+    the games differ. Every move is searched at a fixed depth (or node budget) and the
+    position is kept with its search score unless it is in check, the best move is a
+    capture or the score is a mate. Once the game is over the result is attached to
+    all of its positions.
+
+    Output is a CSV with the columns process-csv reads, plus the game result:
+        fen,evaluation,result
+    evaluation is in centipawns and result is 1.0, 0.5 or 0.0, both from White's point
+    of view. The texel subcommand reads the file as is.
+*/
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use rand::prelude::*;
+
+use crate::{bitboard::{Board, Pieces}, get_move_capture, get_move_piece, get_move_promoted, move_gen::{generate_legal_moves, in_check}, search::MATE_SCORE, transposition::{tt, HASH_SIZE, HASH_TABLE}, uci::{iterative_deepening, NODE_LIMIT}, _csv_fen_to_bitboard::random_legal_move, Side, START_POSITION};
+
+pub struct DatagenOptions {
+    pub games: usize,
+    pub depth: i32,
+    pub nodes: Option<u32>,
+    pub random_plies: usize,
+    pub max_plies: usize,
+    pub adjudicate: i32,
+    pub seed: Option<u64>,
+}
+
+// Plies in a row past the adjudication score before the game is given to that side
+const ADJUDICATION_PLIES: usize = 4;
+
+// Result from White's point of view
+fn result_str(result: f64) -> &'static str {
+    if result > 0.75 {"1.0"} else if result < 0.25 {"0.0"} else {"0.5"}
+}
+
+// Pawn moves, captures and promotions reset the fifty-move counter
+fn resets_halfmove_clock(move_: usize) -> bool {
+    let piece = get_move_piece!(move_);
+    piece == Pieces::P as usize || piece == Pieces::p as usize || get_move_capture!(move_) || get_move_promoted!(move_) != 12
+}
+
+// Random opening, None when it ended the game
+unsafe fn random_opening(plies: usize, rng: &mut StdRng) -> Option<(Board, Vec<u64>)> {
+    let mut board = Board::new_from_fen(START_POSITION);
+    let mut history = vec![board.hash_key];
+
+    for _ in 0..plies {
+        let move_ = random_legal_move(&board, rng)?;
+        board.make_legal_move(move_);
+        history.push(board.hash_key);
+    }
+
+    if generate_legal_moves(&board).count == 0 {
+        return None
+    }
+    Some((board, history))
+}
+
+// Plays one game, returns the kept positions (FEN, White relative score), the result
+// and the average search depth
+unsafe fn play_game(options: &DatagenOptions, rng: &mut StdRng) -> (Vec<(String, i32)>, f64, f64) {
+    let (mut board, mut history) = loop {
+        if let Some(opening) = random_opening(options.random_plies, rng) {
+            break opening
+        }
+    };
+    HASH_TABLE = [tt::new();HASH_SIZE];
+
+    let mut positions = Vec::new();
+    let mut halfmove_clock = 0;
+    let mut winning_plies = 0;
+    let mut losing_plies = 0;
+    let (mut depth_sum, mut searches) = (0, 0);
+
+    let result = loop {
+        if let Some(result) = board.game_result(&history, halfmove_clock) {
+            break result.white_score()
+        }
+        if history.len() > options.max_plies {
+            break 0.5
+        }
+        let checked = in_check(&board);
+
+        // Game so far for the repetition detection of the search, as parse_position stores it
+        let recent = &history[history.len().saturating_sub(100)..];
+        board.repetition_table[..recent.len()].copy_from_slice(recent);
+        board.repetition_index = recent.len();
+
+        let search = iterative_deepening(&mut board, options.depth, false);
+        depth_sum += search.depth;
+        searches += 1;
+
+        if search.best_move == 0 {
+            // The search found no move (stopped too early): finish with a random one, keeping nothing
+            let Some(move_) = random_legal_move(&board, rng) else { break 0.5 };
+            halfmove_clock = if resets_halfmove_clock(move_) {0} else {halfmove_clock + 1};
+            board.make_legal_move(move_);
+            history.push(board.hash_key);
+            continue;
+        }
+        let best_move = search.best_move;
+        let score = if board.side == Side::White {search.score} else {-search.score};
+
+        if !checked && !get_move_capture!(best_move) && score.abs() < MATE_SCORE {
+            positions.push((board.to_fen(), score));
+        }
+
+        // Both sides agree on a decisive score
+        if score >= options.adjudicate { winning_plies += 1 } else { winning_plies = 0 }
+        if score <= -options.adjudicate { losing_plies += 1 } else { losing_plies = 0 }
+        if winning_plies >= ADJUDICATION_PLIES { break 1.0 }
+        if losing_plies >= ADJUDICATION_PLIES { break 0.0 }
+
+        halfmove_clock = if resets_halfmove_clock(best_move) {0} else {halfmove_clock + 1};
+        board.make_move(best_move, false);
+        history.push(board.hash_key);
+    };
+
+    (positions, result, depth_sum as f64 / searches.max(1) as f64)
+}
+
+pub unsafe fn generate_data(output: &str, options: &DatagenOptions) -> Result<(), Box<dyn Error>> {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut writer = BufWriter::new(File::create(output)?);
+    writeln!(writer, "fen,evaluation,result")?;
+
+    NODE_LIMIT = options.nodes;
+
+    let start = Instant::now();
+    let mut total_positions = 0;
+    let mut scores = [0;3];
+
+    for game in 1..=options.games {
+        let (positions, result, depth) = play_game(options, &mut rng);
+
+        for (fen, score) in positions.iter() {
+            writeln!(writer, "{},{},{}", fen, score, result_str(result))?;
+        }
+        writer.flush()?;
+
+        total_positions += positions.len();
+        scores[(result * 2.0) as usize] += 1;
+        println!("Game {}/{}: {}, {} positions, depth {:.1} ({} total, +{} ={} -{}, {:.1}s)",
+            game, options.games, result_str(result), positions.len(), depth, total_positions, scores[2], scores[1], scores[0], start.elapsed().as_secs_f64());
+    }
+
+    NODE_LIMIT = None;
+    println!("Data generation complete. {} positions saved to {}", total_positions, output);
+    Ok(())
+}
@@ -0,0 +1,570 @@
+/******************************************\
+ ==========================================
+              Endgame Tables
+ ==========================================
+\******************************************/
+
+/*
+    Distance-to-mate tables for endings of up to 4 pieces (KQvK, KRvK, KPvK, KBNvK, ...)
+    built by retrograde analysis with the legal move generator.
+
+    A table is named after its material, stronger side first ("KRvKN"), and holds one
+    byte per position. The index packs the square of every piece in 6 bits (White's
+    pieces first, in the order of the name) followed by the side to move:
+        index = side << 6n | ... | square_1 << 6 | square_0
+    Bytes: 0 draw, 255 illegal position, d+1 when the side to move mates (d odd)
+    or is mated (d even) in d plies.
+
+    Generation:
+    - Every position is set up, the side not to move must not be in check. Legal moves
+      staying in the material are counted, moves that capture or promote are scored
+      with the smaller table (generated first, KK/KBK/KNK are draws)
+    - Positions are resolved by increasing distance from the mates. A position is won
+      at d+1 when one move reaches a loss in d, and lost when its last move reaching
+      a win runs out. Predecessors are found by unmoving the pieces of the side that
+      just moved (pawns step back, no uncaptures or unpromotions)
+    - Anything left unresolved is a draw. Castling and en passant are not part of
+      the tables, and the fifty-move rule is ignored
+
+    File format (.egtb): the magic "LCEG" then, gzip compressed, the version byte,
+    the length of the name, the name and the table bytes.
+*/
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::ptr::addr_of;
+use std::time::Instant;
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::{Side, get_move_source, get_move_target, get_move_piece, get_move_promoted, get_move_capture};
+use crate::attacks::{KNIGHT_ATTACKS, KING_ATTACKS, get_bishop_attacks, get_rook_attacks, get_queen_attacks};
+use crate::bitboard::{Board, count_bits, get_ls1b_index};
+use crate::move_gen::{generate_legal_moves, in_check, is_square_attacked};
+use crate::search::{MATE_VALUE, PLY};
+use crate::syzygy::TB_HITS;
+
+const MAGIC:&[u8;4] = b"LCEG";
+const VERSION:u8 = 1;
+pub const MAX_ENDGAME_PIECES:usize = 4;
+
+const DRAW:u8 = 0;
+const ILLEGAL:u8 = 255;
+// Longest distance a byte can hold
+const MAX_DISTANCE:usize = 253;
+// Bucket entries won through a capture or promotion, only taken if still unresolved
+const PENDING:u32 = 1 << 31;
+
+pub static mut ENDGAME_PATH:Option<String> = None;
+pub static mut ENDGAME_TABLES:Vec<EndgameTable> = Vec::new();
+
+pub struct EndgameTable {
+    pub name:String,
+    counts:[u8;12],
+    pieces:Vec<usize>, // Piece of every square slot of the index
+    values:Vec<u8>,
+}
+
+// Piece order inside each side of a name
+const NAME_ORDER:[usize;6] = [5, 4, 3, 2, 1, 0]; // K, Q, R, B, N, P
+const PIECE_VALUES:[u32;6] = [1, 3, 3, 5, 9, 0];
+
+fn piece_counts(bitboards:&[u64;12]) -> [u8;12] {
+    let mut counts = [0u8;12];
+    for piece in 0..12 {
+        counts[piece] = count_bits(bitboards[piece]) as u8;
+    }
+    counts
+}
+
+fn flip_counts(counts:&[u8;12]) -> [u8;12] {
+    let mut flipped = [0u8;12];
+    for piece in 0..12 {
+        flipped[(piece + 6) % 12] = counts[piece];
+    }
+    flipped
+}
+
+// Same rule as Board::insufficient_material
+fn insufficient(counts:&[u8;12]) -> bool {
+    [0, 3, 4, 6, 9, 10].iter().all(|&piece| counts[piece] == 0)
+        && [1, 2, 7, 8].iter().map(|&piece| counts[piece]).sum::<u8>() <= 1
+}
+
+// Stronger side (more pieces, then more material) as White
+fn canonical(counts:&[u8;12]) -> [u8;12] {
+    let strength = |offset:usize| (
+        (0..5).map(|piece| counts[piece + offset] as u32).sum::<u32>(),
+        (0..5).map(|piece| counts[piece + offset] as u32 * PIECE_VALUES[piece]).sum::<u32>(),
+    );
+    if strength(6) > strength(0) { flip_counts(counts) } else { *counts }
+}
+
+fn material_name(counts:&[u8;12]) -> String {
+    let side = |offset:usize| NAME_ORDER.iter()
+        .flat_map(|&piece| std::iter::repeat("PNBRQK".as_bytes()[piece] as char).take(counts[piece + offset] as usize))
+        .collect::<String>();
+    format!("{}v{}", side(0), side(6))
+}
+
+// "KBNvK" to piece counts, one king per side
+pub fn parse_material(name:&str) -> Result<[u8;12], String> {
+    let (white, black) = name.trim().split_once('v').ok_or(format!("invalid material {} (expected e.g. KQvK)", name))?;
+    let mut counts = [0u8;12];
+    for (side, offset) in [(white, 0), (black, 6)] {
+        for c in side.chars() {
+            let piece = "PNBRQK".find(c.to_ascii_uppercase()).ok_or(format!("invalid piece {} in {}", c, name))?;
+            counts[piece + offset] += 1;
+        }
+    }
+    if counts[5] != 1 || counts[11] != 1 {
+        return Err(format!("{} needs one king per side", name))
+    }
+    if counts.iter().map(|&count| count as usize).sum::<usize>() > MAX_ENDGAME_PIECES {
+        return Err(format!("{} has more than {} pieces", name, MAX_ENDGAME_PIECES))
+    }
+    Ok(counts)
+}
+
+impl EndgameTable {
+    fn new(counts:[u8;12], values:Vec<u8>) -> EndgameTable {
+        let pieces = [0, 6].iter()
+            .flat_map(|&offset| NAME_ORDER.iter().flat_map(move |&piece| std::iter::repeat(piece + offset).take(counts[piece + offset] as usize)))
+            .collect();
+        EndgameTable { name: material_name(&counts), counts, pieces, values }
+    }
+
+    fn size(&self) -> usize {
+        2 << (6 * self.pieces.len())
+    }
+
+    // Index of a position with this material, colours swapped (and ranks mirrored) when flip
+    fn index(&self, bitboards:&[u64;12], white_to_move:bool, flip:bool) -> usize {
+        let mut bitboards = *bitboards;
+        let mut index = 0;
+        for (slot, &piece) in self.pieces.iter().enumerate() {
+            let piece = if flip {(piece + 6) % 12} else {piece};
+            let square = get_ls1b_index(bitboards[piece]);
+            bitboards[piece] &= bitboards[piece] - 1;
+            index |= (if flip {square ^ 56} else {square}) << (6 * slot);
+        }
+        let black_to_move = white_to_move == flip;
+        index | (black_to_move as usize) << (6 * self.pieces.len())
+    }
+
+    pub fn write(&self, path:&Path) -> Result<(), Box<dyn Error>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(&[VERSION, self.name.len() as u8])?;
+        encoder.write_all(self.name.as_bytes())?;
+        encoder.write_all(&self.values)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    pub fn read(path:&Path) -> Result<EndgameTable, Box<dyn Error>> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0u8;4];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format!("{} is not an endgame table", path.display()).into())
+        }
+
+        let mut decoder = GzDecoder::new(file);
+        let mut header = [0u8;2];
+        decoder.read_exact(&mut header)?;
+        if header[0] != VERSION {
+            return Err(format!("{} has version {}, expected {}", path.display(), header[0], VERSION).into())
+        }
+        let mut name = vec![0u8; header[1] as usize];
+        decoder.read_exact(&mut name)?;
+        let counts = parse_material(&String::from_utf8(name)?)?;
+
+        let mut table = EndgameTable::new(counts, Vec::new());
+        decoder.read_to_end(&mut table.values)?;
+        if table.values.len() != table.size() {
+            return Err(format!("{} holds {} positions, expected {}", path.display(), table.values.len(), table.size()).into())
+        }
+        Ok(table)
+    }
+}
+
+// Table byte of a position, None when no table has its material
+fn lookup(tables:&[EndgameTable], bitboards:&[u64;12], white_to_move:bool) -> Option<u8> {
+    let counts = piece_counts(bitboards);
+    if insufficient(&counts) {
+        return Some(DRAW)
+    }
+
+    let flipped = flip_counts(&counts);
+    tables.iter().find_map(|table| {
+        if table.counts == counts {
+            Some(table.values[table.index(bitboards, white_to_move, false)])
+        } else if table.counts == flipped {
+            Some(table.values[table.index(bitboards, white_to_move, true)])
+        } else {
+            None
+        }
+    })
+}
+
+// Bitboards of an index, None when two pieces share a square or a pawn is on a back rank
+fn decode(pieces:&[usize], index:usize) -> Option<[u64;12]> {
+    let mut bitboards = [0u64;12];
+    let mut occupancy = 0u64;
+    for (slot, &piece) in pieces.iter().enumerate() {
+        let square = (index >> (6 * slot)) & 63;
+        if occupancy & (1 << square) != 0 || (piece % 6 == 0 && !(8..56).contains(&square)) {
+            return None
+        }
+        occupancy |= 1 << square;
+        bitboards[piece] |= 1 << square;
+    }
+    Some(bitboards)
+}
+
+fn set_board(board:&mut Board, bitboards:&[u64;12], white_to_move:bool) {
+    board.bitboards = *bitboards;
+    board.occupancies[0] = bitboards[..6].iter().fold(0, |occupancy, bitboard| occupancy | bitboard);
+    board.occupancies[1] = bitboards[6..].iter().fold(0, |occupancy, bitboard| occupancy | bitboard);
+    board.occupancies[2] = board.occupancies[0] | board.occupancies[1];
+    board.side = if white_to_move {Side::White} else {Side::Black};
+    board.castle = 0;
+    board.en_passant = None;
+}
+
+// Positions (same material) from which the side that just moved reached index
+unsafe fn predecessors(pieces:&[usize], index:usize, previous:&mut Vec<usize>) {
+    previous.clear();
+    let side_shift = 6 * pieces.len();
+    let white_to_move = index >> side_shift == 0;
+    let occupancy = (0..pieces.len()).fold(0u64, |occupancy, slot| occupancy | 1 << ((index >> (6 * slot)) & 63));
+
+    for (slot, &piece) in pieces.iter().enumerate() {
+        if (piece < 6) == white_to_move {
+            continue
+        }
+
+        let square = (index >> (6 * slot)) & 63;
+        let mut sources = match piece {
+            // White pawns came from below (higher squares), black pawns from above
+            0 => {
+                let mut sources = 0u64;
+                if square + 8 < 56 && occupancy & (1 << (square + 8)) == 0 {
+                    sources |= 1 << (square + 8);
+                    if (32..40).contains(&square) && occupancy & (1 << (square + 16)) == 0 {
+                        sources |= 1 << (square + 16);
+                    }
+                }
+                sources
+            },
+            6 => {
+                let mut sources = 0u64;
+                if square >= 16 && occupancy & (1 << (square - 8)) == 0 {
+                    sources |= 1 << (square - 8);
+                    if (24..32).contains(&square) && occupancy & (1 << (square - 16)) == 0 {
+                        sources |= 1 << (square - 16);
+                    }
+                }
+                sources
+            },
+            _ => (match piece % 6 {
+                1 => KNIGHT_ATTACKS[square],
+                2 => get_bishop_attacks(square, occupancy),
+                3 => get_rook_attacks(square, occupancy),
+                4 => get_queen_attacks(square, occupancy),
+                _ => KING_ATTACKS[square],
+            }) & !occupancy,
+        };
+
+        let base = (index & !(63 << (6 * slot)) & !(1 << side_shift)) | (white_to_move as usize) << side_shift;
+        while sources != 0 {
+            let source = get_ls1b_index(sources);
+            previous.push(base | source << (6 * slot));
+            sources &= sources - 1;
+        }
+    }
+}
+
+// Retrograde analysis of one material, the tables it captures or promotes into must be in tables
+unsafe fn retrograde(counts:[u8;12], tables:&[EndgameTable]) -> EndgameTable {
+    let mut table = EndgameTable::new(counts, Vec::new());
+    let size = table.size();
+    let side_shift = 6 * table.pieces.len();
+
+    let mut values = vec![ILLEGAL; size];
+    // Moves staying in the table not yet known to lose
+    let mut remaining = vec![0u8; size];
+    // Longest loss through captures and promotions, and whether one of them draws or wins
+    let mut exit_loss = vec![0u8; size];
+    let mut avoid_loss = vec![false; size];
+    let mut buckets:Vec<Vec<u32>> = vec![Vec::new(); MAX_DISTANCE + 1];
+
+    let mut board = Board::new();
+    for index in 0..size {
+        let bitboards = match decode(&table.pieces, index) {
+            Some(bitboards) => bitboards,
+            None => continue,
+        };
+        let white_to_move = index >> side_shift == 0;
+        set_board(&mut board, &bitboards, white_to_move);
+
+        // The side that just moved can't be left in check
+        let their_king = get_ls1b_index(bitboards[if white_to_move {11} else {5}]);
+        if is_square_attacked(&board, their_king, white_to_move) {
+            continue
+        }
+        values[index] = DRAW;
+
+        let moves = generate_legal_moves(&board);
+        if moves.count == 0 {
+            // Checkmate, stalemate stays a draw
+            if in_check(&board) {
+                values[index] = 1;
+                buckets[0].push(index as u32);
+            }
+            continue
+        }
+
+        let mut exit_win = usize::MAX;
+        for &move_ in &moves.moves[..moves.count] {
+            let promoted = get_move_promoted!(move_);
+            if !get_move_capture!(move_) && promoted == 12 {
+                remaining[index] += 1;
+                continue
+            }
+
+            let (source, target, piece) = (get_move_source!(move_), get_move_target!(move_), get_move_piece!(move_));
+            let mut next = bitboards;
+            next[piece] &= !(1u64 << source);
+            for captured in if white_to_move {6..12} else {0..6} {
+                next[captured] &= !(1u64 << target);
+            }
+            next[if promoted == 12 {piece} else {promoted}] |= 1 << target;
+
+            match lookup(tables, &next, !white_to_move) {
+                Some(value) if value != DRAW && value != ILLEGAL => {
+                    let distance = value as usize - 1;
+                    if distance % 2 == 0 {
+                        exit_win = exit_win.min(distance + 1);
+                        avoid_loss[index] = true;
+                    } else {
+                        exit_loss[index] = exit_loss[index].max((distance + 1).min(MAX_DISTANCE) as u8);
+                    }
+                },
+                _ => avoid_loss[index] = true,
+            }
+        }
+
+        if exit_win != usize::MAX {
+            buckets[exit_win.min(MAX_DISTANCE)].push(index as u32 | PENDING);
+        } else if remaining[index] == 0 && !avoid_loss[index] {
+            let distance = exit_loss[index] as usize;
+            values[index] = distance as u8 + 1;
+            buckets[distance].push(index as u32);
+        }
+    }
+
+    let mut previous = Vec::new();
+    for distance in 0..=MAX_DISTANCE {
+        let bucket = std::mem::take(&mut buckets[distance]);
+        for entry in bucket {
+            let index = (entry & !PENDING) as usize;
+            if entry & PENDING != 0 {
+                if values[index] != DRAW {
+                    continue
+                }
+                values[index] = distance as u8 + 1;
+            }
+
+            let next_distance = (distance + 1).min(MAX_DISTANCE);
+            predecessors(&table.pieces, index, &mut previous);
+            for &position in &previous {
+                if values[position] != DRAW {
+                    continue
+                }
+                if distance % 2 == 0 {
+                    // One move to a lost position
+                    values[position] = next_distance as u8 + 1;
+                    buckets[next_distance].push(position as u32);
+                } else {
+                    remaining[position] -= 1;
+                    if remaining[position] == 0 && !avoid_loss[position] {
+                        let loss = next_distance.max(exit_loss[position] as usize);
+                        values[position] = loss as u8 + 1;
+                        buckets[loss].push(position as u32);
+                    }
+                }
+            }
+        }
+    }
+
+    table.values = values;
+    table
+}
+
+// Generates a material and, first, every table it captures or promotes into
+pub unsafe fn generate_table(counts:[u8;12], tables:&mut Vec<EndgameTable>) -> Vec<String> {
+    let counts = canonical(&counts);
+    let flipped = flip_counts(&counts);
+    if insufficient(&counts) || tables.iter().any(|table| table.counts == counts || table.counts == flipped) {
+        return Vec::new()
+    }
+
+    let mut generated = Vec::new();
+    for piece in (0..12).filter(|&piece| piece % 6 != 5 && counts[piece] != 0) {
+        let mut captured = counts;
+        captured[piece] -= 1;
+        generated.extend(generate_table(captured, tables));
+
+        if piece % 6 == 0 {
+            for promoted in piece+1..piece+5 {
+                let mut promotion = captured;
+                promotion[promoted] += 1;
+                generated.extend(generate_table(promotion, tables));
+            }
+        }
+    }
+
+    let start = Instant::now();
+    let table = retrograde(counts, tables);
+    let longest = table.values.iter().filter(|&&value| value != ILLEGAL).max().map_or(0, |&value| value.saturating_sub(1));
+    let legal = table.values.iter().filter(|&&value| value != ILLEGAL).count();
+    let decided = table.values.iter().filter(|&&value| value != ILLEGAL && value != DRAW).count();
+    println!("{}: {} positions, {} decided, longest mate in {} plies ({:.1}s)", table.name, legal, decided, longest, start.elapsed().as_secs_f64());
+
+    generated.push(table.name.clone());
+    tables.push(table);
+    generated
+}
+
+// Every .egtb file of a directory
+pub fn load_tables(directory:&str) -> Result<Vec<EndgameTable>, Box<dyn Error>> {
+    let mut tables = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "egtb") {
+            tables.push(EndgameTable::read(&path)?);
+        }
+    }
+    tables.sort_by(|a, b| a.pieces.len().cmp(&b.pieces.len()).then_with(|| a.name.cmp(&b.name)));
+    Ok(tables)
+}
+
+// endgame-gen: generates the materials (and the tables they need) into directory
+pub unsafe fn generate_tables(materials:&[String], directory:&str) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(directory)?;
+    let mut tables = load_tables(directory)?;
+
+    for material in materials {
+        for name in generate_table(parse_material(material)?, &mut tables) {
+            let table = tables.iter().find(|table| table.name == name).unwrap();
+            table.write(&Path::new(directory).join(format!("{}.egtb", name)))?;
+        }
+    }
+    Ok(())
+}
+
+// EndgamePath option, "<empty>" clears it
+pub unsafe fn set_endgame_path(path:&str) {
+    ENDGAME_PATH = None;
+    ENDGAME_TABLES = Vec::new();
+
+    if path.is_empty() || path == "<empty>" {
+        return
+    }
+
+    match load_tables(path) {
+        Ok(tables) => {
+            let names:Vec<&str> = tables.iter().map(|table| table.name.as_str()).collect();
+            println!("info string loaded {} endgame tables: {}", tables.len(), names.join(" "));
+            ENDGAME_PATH = Some(path.to_string());
+            ENDGAME_TABLES = tables;
+        },
+        Err(e) => println!("info string cannot load endgame tables from {}: {}", path, e),
+    }
+}
+
+// Exact score for the side to move, mates counted from the root like the search does
+pub unsafe fn probe_endgame(board:&Board) -> Option<i32> {
+    let tables = &*addr_of!(ENDGAME_TABLES);
+    if tables.is_empty() || board.castle != 0 || board.en_passant.is_some() || count_bits(board.occupancies[2]) > MAX_ENDGAME_PIECES {
+        return None
+    }
+
+    // Drawn without a table, not a table hit
+    if insufficient(&piece_counts(&board.bitboards)) {
+        return Some(0)
+    }
+
+    let value = lookup(tables, &board.bitboards, board.side == Side::White)?;
+    if value == ILLEGAL {
+        return None
+    }
+
+    TB_HITS += 1;
+    if value == DRAW {
+        return Some(0)
+    }
+    let distance = value as i32 - 1;
+    Some(if distance % 2 == 1 { MATE_VALUE - PLY as i32 - distance } else { -MATE_VALUE + PLY as i32 + distance })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+
+    // KPvK and the tables it promotes into: KQvK, KRvK
+    fn tables() -> &'static [EndgameTable] {
+        static TABLES:OnceLock<Vec<EndgameTable>> = OnceLock::new();
+        TABLES.get_or_init(|| unsafe {
+            crate::init_all_vars();
+            let mut tables = Vec::new();
+            generate_table(parse_material("KPvK").unwrap(), &mut tables);
+            tables
+        })
+    }
+
+    // Longest mate by the side to move, in plies
+    fn longest_mate(name:&str) -> usize {
+        let table = tables().iter().find(|table| table.name == name).unwrap();
+        table.values.iter().filter(|&&value| value != ILLEGAL && value != DRAW).map(|&value| value as usize - 1).filter(|distance| distance % 2 == 1).max().unwrap()
+    }
+
+    fn value(fen:&str) -> Option<u8> {
+        let board = Board::new_from_fen(fen);
+        lookup(tables(), &board.bitboards, board.side == Side::White)
+    }
+
+    #[test]
+    fn longest_mates_match_the_known_lengths() {
+        // Mate in 10 and in 16 moves
+        assert_eq!(longest_mate("KQvK"), 19);
+        assert_eq!(longest_mate("KRvK"), 31);
+    }
+
+    #[test]
+    fn rook_pawn_with_the_king_in_the_corner_is_a_draw() {
+        assert_eq!(value("k7/8/8/8/8/8/P7/K7 w - - 0 1"), Some(DRAW));
+        assert_eq!(value("k7/8/8/8/8/8/P7/K7 b - - 0 1"), Some(DRAW));
+        // The same pawn with the defending king far away queens
+        assert_ne!(value("7k/8/8/8/8/8/P7/K7 w - - 0 1"), Some(DRAW));
+    }
+
+    #[test]
+    fn black_pawn_endings_use_the_flipped_table() {
+        let table = tables().iter().find(|table| table.name == "KPvK").unwrap();
+        let board = Board::new_from_fen("8/8/8/4k3/8/8/4p3/K7 b - - 0 1");
+        let mirrored = Board::new_from_fen("k7/4P3/8/8/4K3/8/8/8 w - - 0 1");
+
+        // Colours swapped and ranks mirrored, White to move
+        let index = table.index(&board.bitboards, false, true);
+        assert_eq!(index, table.index(&mirrored.bitboards, true, false));
+        assert_eq!(decode(&table.pieces, index), Some(mirrored.bitboards));
+        assert_eq!(value("8/8/8/4k3/8/8/4p3/K7 b - - 0 1"), value("k7/4P3/8/8/4K3/8/8/8 w - - 0 1"));
+    }
+}
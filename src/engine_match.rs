@@ -0,0 +1,270 @@
+/******************************************\
+ ==========================================
+               Engine Match
+ ==========================================
+\******************************************/
+
+/*
+    Plays two engines against each other through UCI. An engine is either a linear
+    coefficients file, played by this binary ("uci -i file"), or "exec:command" for
+    any UCI engine. Every opening is played twice with the colours swapped.
+
+    Games end by the board rules (Board::game_result), the move limit, or a loss on
+    time, illegal move or engine failure. Results are reported from engine 1's point
+    of view, with the Elo difference, its 95% interval and the log-likelihood ratio of
+    the SPRT between H0: elo = elo0 and H1: elo = elo1 (normal approximation of the
+    game score). The match stops as soon as the SPRT accepts either hypothesis.
+*/
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand::prelude::*;
+
+use crate::{bitboard::{Board, Pieces}, get_move_capture, get_move_piece, moves::move_str, uci::parse_move, uci_client::{engine_command, GoLimits, UciEngine}, _csv_fen_to_bitboard::random_legal_move, Side, START_POSITION};
+
+// Extra time allowed for an answer before a time control loss
+const TIME_MARGIN:Duration = Duration::from_millis(1000);
+// Time allowed per move at fixed depth
+const DEPTH_TIMEOUT:Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+pub struct TimeControl {
+    pub base_ms:u64,
+    pub increment_ms:u64,
+}
+
+// "base+increment" in seconds, such as "10+0.1" or "60"
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(text:&str) -> Result<TimeControl, String> {
+        let (base, increment) = text.split_once('+').unwrap_or((text, "0"));
+        let seconds = |value:&str| value.trim().parse::<f64>().ok().filter(|value| *value >= 0.0).ok_or(format!("bad time control {}", text));
+        Ok(TimeControl { base_ms: (seconds(base)? * 1000.0) as u64, increment_ms: (seconds(increment)? * 1000.0) as u64 })
+    }
+}
+
+pub struct MatchOptions {
+    pub games:usize,
+    pub time_control:TimeControl,
+    pub depth:Option<u32>,
+    pub openings:Option<String>,
+    pub random_plies:usize,
+    pub max_plies:usize,
+    pub elo0:f64,
+    pub elo1:f64,
+    pub alpha:f64,
+    pub beta:f64,
+    pub seed:Option<u64>,
+}
+
+// One FEN per line (EPD operations after the four first fields are ignored)
+fn read_openings(path:&str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut openings = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let fields:Vec<&str> = line.split_whitespace().take(4).collect();
+        if fields.len() < 4 || fields[0].starts_with('#') { continue; }
+        openings.push(format!("{} 0 1", fields.join(" ")));
+    }
+    if openings.is_empty() {
+        return Err(format!("no openings in {}", path).into())
+    }
+    Ok(openings)
+}
+
+unsafe fn random_opening(plies:usize, rng:&mut StdRng) -> String {
+    loop {
+        let mut board = Board::new_from_fen(START_POSITION);
+        let finished = (0..plies).any(|_| match random_legal_move(&board, rng) {
+            Some(move_) => { board.make_legal_move(move_); false },
+            None => true,
+        });
+        if !finished && board.game_result(&[board.hash_key], 0).is_none() {
+            return board.to_fen()
+        }
+    }
+}
+
+fn is_move_string(move_string:&str) -> bool {
+    let bytes = move_string.as_bytes();
+    (bytes.len() == 4 || (bytes.len() == 5 && b"qrbn".contains(&bytes[4])))
+        && (b'a'..=b'h').contains(&bytes[0]) && (b'1'..=b'8').contains(&bytes[1])
+        && (b'a'..=b'h').contains(&bytes[2]) && (b'1'..=b'8').contains(&bytes[3])
+}
+
+// Plays one game, returns White's score and how it ended
+unsafe fn play_game(white:&mut UciEngine, black:&mut UciEngine, opening:&str, options:&MatchOptions) -> Result<(f64, String), Box<dyn Error>> {
+    white.new_game()?;
+    black.new_game()?;
+
+    let mut board = Board::new_from_fen(opening);
+    let mut history = vec![board.hash_key];
+    let mut moves:Vec<String> = Vec::new();
+    let mut halfmove_clock = 0;
+    let mut clocks = [options.time_control.base_ms as i64; 2];
+
+    loop {
+        if let Some(result) = board.game_result(&history, halfmove_clock) {
+            return Ok((result.white_score(), result.reason().to_string()))
+        }
+        if moves.len() >= options.max_plies {
+            return Ok((0.5, "move limit".to_string()))
+        }
+
+        let us = if board.side == Side::White {0} else {1};
+        let engine = if us == 0 {&mut *white} else {&mut *black};
+        let loss = if us == 0 {0.0} else {1.0};
+
+        let (limits, timeout) = match options.depth {
+            Some(depth) => (GoLimits { depth: Some(depth), ..GoLimits::default() }, DEPTH_TIMEOUT),
+            None => {
+                let increment = Some(options.time_control.increment_ms);
+                let limits = GoLimits { wtime: Some(clocks[0].max(1) as u64), btime: Some(clocks[1].max(1) as u64), winc: increment, binc: increment, ..GoLimits::default() };
+                (limits, Duration::from_millis(clocks[us].max(0) as u64) + TIME_MARGIN)
+            },
+        };
+
+        let start = Instant::now();
+        let answer = engine.set_position(Some(opening), &moves).map_err(|e| e.into()).and_then(|_| engine.go(&limits, timeout));
+        let elapsed = start.elapsed().as_millis() as i64;
+
+        let move_string = match answer {
+            Ok(report) => report.best_move,
+            Err(e) => {
+                let _ = engine.stop();
+                return Ok((loss, format!("{} failed: {}", engine.name, e)))
+            }
+        };
+        if options.depth.is_none() {
+            clocks[us] -= elapsed;
+            if clocks[us] + (TIME_MARGIN.as_millis() as i64) < 0 {
+                return Ok((loss, format!("{} lost on time", engine.name)))
+            }
+            clocks[us] += options.time_control.increment_ms as i64;
+        }
+
+        let move_ = if is_move_string(&move_string) {parse_move(&board, &move_string)} else {0};
+        if move_ == 0 {
+            return Ok((loss, format!("{} played an illegal move {}", engine.name, move_string)))
+        }
+
+        let pawn_move = get_move_piece!(move_) == Pieces::P as usize || get_move_piece!(move_) == Pieces::p as usize;
+        halfmove_clock = if pawn_move || get_move_capture!(move_) {0} else {halfmove_clock + 1};
+        board.make_legal_move(move_);
+        history.push(board.hash_key);
+        moves.push(move_str(move_));
+    }
+}
+
+fn elo_from_score(score:f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn score_from_elo(elo:f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// Mean score and variance of a single game
+fn score_stats(wins:usize, draws:usize, losses:usize) -> (f64, f64) {
+    let games = (wins + draws + losses) as f64;
+    let score = (wins as f64 + 0.5 * draws as f64) / games;
+    let variance = (wins as f64 * (1.0 - score).powi(2) + draws as f64 * (0.5 - score).powi(2) + losses as f64 * score.powi(2)) / games;
+    (score, variance)
+}
+
+// Elo difference and half width of its 95% interval
+pub fn elo_estimate(wins:usize, draws:usize, losses:usize) -> (f64, f64) {
+    let games = (wins + draws + losses) as f64;
+    let (score, variance) = score_stats(wins, draws, losses);
+    let error = 1.96 * (variance / games).sqrt();
+
+    // Infinite while one engine has every point
+    let low = elo_from_score((score - error).clamp(0.0, 1.0));
+    let high = elo_from_score((score + error).clamp(0.0, 1.0));
+    let margin = if high.is_infinite() || low.is_infinite() {f64::INFINITY} else {(high - low) / 2.0};
+    (elo_from_score(score), margin)
+}
+
+pub fn sprt_llr(wins:usize, draws:usize, losses:usize, elo0:f64, elo1:f64) -> f64 {
+    let games = (wins + draws + losses) as f64;
+    let (score, variance) = score_stats(wins, draws, losses);
+    if variance <= 0.0 {
+        return 0.0
+    }
+    let (score0, score1) = (score_from_elo(elo0), score_from_elo(elo1));
+    games * (score1 - score0) * (2.0 * score - score0 - score1) / (2.0 * variance)
+}
+
+pub unsafe fn run_match(engine1:&str, engine2:&str, options:&MatchOptions) -> Result<(), Box<dyn Error>> {
+    let start = |spec:&str| -> Result<UciEngine, Box<dyn Error>> {
+        let (program, args) = engine_command(spec)?;
+        UciEngine::start(&program, &args)
+    };
+    let mut engines = [start(engine1)?, start(engine2)?];
+    // Coefficient files are named after the file, both would be called as this engine
+    for (engine, spec) in engines.iter_mut().zip([engine1, engine2]) {
+        if !spec.starts_with("exec:") {
+            engine.name = spec.to_string();
+        }
+    }
+    if engines[0].name == engines[1].name {
+        engines[0].name += " #1";
+        engines[1].name += " #2";
+    }
+    println!("Match {} vs {}, {} games", engines[0].name, engines[1].name, options.games);
+
+    let openings = match &options.openings {
+        Some(path) => read_openings(path)?,
+        None => Vec::new(),
+    };
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let lower_bound = (options.beta / (1.0 - options.alpha)).ln();
+    let upper_bound = ((1.0 - options.beta) / options.alpha).ln();
+    let (mut wins, mut draws, mut losses) = (0, 0, 0);
+    let mut opening = String::new();
+
+    for game in 0..options.games {
+        // Each opening is played with both colour assignments
+        if game % 2 == 0 {
+            opening = if openings.is_empty() {random_opening(options.random_plies, &mut rng)} else {openings[(game / 2) % openings.len()].clone()};
+        }
+        let engine1_white = game % 2 == 0;
+
+        let [first, second] = &mut engines;
+        let (white_score, reason) = if engine1_white {play_game(first, second, &opening, options)?} else {play_game(second, first, &opening, options)?};
+        let score = if engine1_white {white_score} else {1.0 - white_score};
+        match score {
+            score if score > 0.75 => wins += 1,
+            score if score < 0.25 => losses += 1,
+            _ => draws += 1,
+        }
+
+        let (elo, margin) = elo_estimate(wins, draws, losses);
+        let llr = sprt_llr(wins, draws, losses, options.elo0, options.elo1);
+        let result = if white_score > 0.75 {"1-0"} else if white_score < 0.25 {"0-1"} else {"1/2-1/2"};
+        println!("Game {}/{}: {} vs {} {} ({}) | +{} ={} -{} | Elo {:.1} +/- {:.1} | LLR {:.2} ({:.2}, {:.2})",
+            game + 1, options.games, engines[if engine1_white {0} else {1}].name, engines[if engine1_white {1} else {0}].name, result, reason,
+            wins, draws, losses, elo, margin, llr, lower_bound, upper_bound);
+
+        if llr >= upper_bound {
+            println!("SPRT: H1 accepted, {} is stronger (elo {} -> {})", engines[0].name, options.elo0, options.elo1);
+            return Ok(())
+        }
+        if llr <= lower_bound {
+            println!("SPRT: H0 accepted, {} is not stronger (elo {} -> {})", engines[0].name, options.elo0, options.elo1);
+            return Ok(())
+        }
+    }
+
+    println!("SPRT: no decision after {} games", options.games);
+    Ok(())
+}
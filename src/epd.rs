@@ -0,0 +1,148 @@
+/******************************************\
+ ==========================================
+              EPD Test Suites
+ ==========================================
+\******************************************/
+
+/*
+    Runs tactical and positional suites (WAC, STS, ...) written as EPD:
+
+        <placement> <side> <castling> <en passant> bm Qg6; am Rxb2; id "WAC.001";
+
+    bm lists the moves that solve the position, am the moves that must be avoided,
+    both in SAN. Every position is searched from a cleared transposition table with
+    the given time and/or depth. The time to solution is the time of the first
+    iteration from which every later iteration kept a solving move.
+*/
+
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::{bitboard::Board, moves::{move_san, parse_san}, transposition::{tt, HASH_SIZE, HASH_TABLE}, uci::{iterative_deepening, set_search_limits}};
+
+// Depth limit of time limited searches
+const MAX_SEARCH_DEPTH:i32 = 32;
+
+pub struct EpdEntry {
+    pub fen:String,
+    pub id:Option<String>,
+    pub best_moves:Vec<String>,
+    pub avoid_moves:Vec<String>,
+}
+
+// Operations are separated by ';' outside of quoted strings
+fn split_operations(text:&str) -> Vec<String> {
+    let mut operations = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in text.chars() {
+        match c {
+            '"' => { quoted = !quoted; current.push(c); },
+            ';' if !quoted => operations.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    operations.push(current);
+
+    operations.into_iter().map(|operation| operation.trim().to_string()).filter(|operation| !operation.is_empty()).collect()
+}
+
+pub fn parse_epd(line:&str) -> Option<EpdEntry> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None
+    }
+
+    let mut fields = line.splitn(5, char::is_whitespace);
+    let fen_fields:Vec<&str> = fields.by_ref().take(4).collect();
+    if fen_fields.len() < 4 {
+        return None
+    }
+
+    let mut entry = EpdEntry { fen: format!("{} 0 1", fen_fields.join(" ")), id: None, best_moves: Vec::new(), avoid_moves: Vec::new() };
+    for operation in split_operations(fields.next().unwrap_or("")) {
+        let (opcode, operands) = operation.split_once(char::is_whitespace).unwrap_or((&operation, ""));
+        let operands = operands.trim();
+        match opcode {
+            "bm" => entry.best_moves.extend(operands.split_whitespace().map(str::to_string)),
+            "am" => entry.avoid_moves.extend(operands.split_whitespace().map(str::to_string)),
+            "id" => entry.id = Some(operands.trim_matches('"').to_string()),
+            _ => (),
+        }
+    }
+
+    Some(entry)
+}
+
+pub unsafe fn run_epd_suite(path:&str, depth:Option<i32>, movetime_ms:Option<u64>) -> Result<(), Box<dyn Error>> {
+    let entries:Vec<EpdEntry> = fs::read_to_string(path)?.lines().filter_map(parse_epd).collect();
+    let depth = depth.unwrap_or(MAX_SEARCH_DEPTH);
+
+    let start = Instant::now();
+    let mut total_nodes:u64 = 0;
+    let mut solved = 0;
+    let mut solution_time = Duration::ZERO;
+    let mut skipped = 0;
+    let mut failures:Vec<String> = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let id = entry.id.clone().unwrap_or_else(|| format!("#{}", index + 1));
+        let mut board = Board::new_from_fen(&entry.fen);
+
+        let best_moves:Vec<usize> = entry.best_moves.iter().map(|san| parse_san(&board, san)).collect();
+        let avoid_moves:Vec<usize> = entry.avoid_moves.iter().map(|san| parse_san(&board, san)).collect();
+        if (best_moves.is_empty() && avoid_moves.is_empty()) || best_moves.contains(&0) || avoid_moves.contains(&0) {
+            println!("{:>4}/{} {:<12} skipped, no bm/am or a move is not legal here", index + 1, entries.len(), id);
+            skipped += 1;
+            continue;
+        }
+        let is_solution = |move_:usize| (best_moves.is_empty() || best_moves.contains(&move_)) && !avoid_moves.contains(&move_);
+
+        HASH_TABLE = [tt::new();HASH_SIZE];
+        set_search_limits(movetime_ms, None);
+        let result = iterative_deepening(&mut board, depth, false);
+        total_nodes += result.nodes as u64;
+
+        let found = if result.best_move == 0 {"(none)".to_string()} else {move_san(&board, result.best_move)};
+        let expected = [("bm", &entry.best_moves), ("am", &entry.avoid_moves)].iter()
+            .filter(|(_, moves)| !moves.is_empty())
+            .map(|(opcode, moves)| format!("{} {}", opcode, moves.join(" ")))
+            .collect::<Vec<_>>().join("; ");
+
+        // Earliest iteration after which the solution was never dropped
+        let kept_from = result.iterations.iter().rposition(|&(_, move_, _)| !is_solution(move_)).map_or(0, |last_miss| last_miss + 1);
+        if is_solution(result.best_move) && kept_from < result.iterations.len() {
+            let (solved_depth, _, time) = result.iterations[kept_from];
+            solved += 1;
+            solution_time += time;
+            println!("{:>4}/{} {:<12} solved  {:<8} ({})  depth {}  {:.2}s", index + 1, entries.len(), id, found, expected, solved_depth, time.as_secs_f64());
+        } else {
+            println!("{:>4}/{} {:<12} failed  {:<8} ({})  depth {}", index + 1, entries.len(), id, found, expected, result.depth);
+            failures.push(format!("{:<12} played {:<8} expected {:<20} {}", id, found, expected, entry.fen));
+        }
+    }
+
+    set_search_limits(None, None);
+
+    let tested = entries.len() - skipped;
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("\nSolved {}/{} ({:.1}%)", solved, tested, 100.0 * solved as f64 / tested.max(1) as f64);
+    if solved > 0 {
+        println!("Average time to solution {:.2}s", solution_time.as_secs_f64() / solved as f64);
+    }
+    if skipped > 0 {
+        println!("Skipped {} positions", skipped);
+    }
+    println!("Nodes {}  time {:.1}s  nps {:.0}", total_nodes, elapsed, total_nodes as f64 / elapsed.max(1e-9));
+
+    if !failures.is_empty() {
+        println!("\nFailures:");
+        for failure in failures.iter() {
+            println!("  {}", failure);
+        }
+    }
+
+    Ok(())
+}
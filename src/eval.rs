@@ -1,8 +1,8 @@
-use std::{net::TcpStream, sync::Mutex};
+use std::{error::Error, ptr::addr_of};
 
-use ndarray::Array1;
+use ndarray::Array2;
 
-use crate::{attacks::{get_bishop_attacks, get_queen_attacks, KING_ATTACKS}, bitboard::{count_bits, get_ls1b_index, print_bitboard, Board}, get_bit, pop_bit, set_bit, Side, Square::{self, *}, _csv_fen_to_bitboard::convert_board_to_csv, _linear_regression::{self, LinearModel}, _neural_network::{communicate, load, predict, Layer}};
+use crate::{attacks::{get_bishop_attacks, get_queen_attacks, KING_ATTACKS}, bitboard::{count_bits, get_ls1b_index, print_bitboard, Board}, get_bit, pop_bit, set_bit, Side, Square::{self, *}, _csv_fen_to_bitboard::{convert_board_to_csv, FEATURE_COUNT}, linear_score::LINEAR_SCALE, nnue::{evaluate_nnue, network_loaded}, external_eval::{ExternalEvaluator, EXTERNAL_ADDRESS, EXTERNAL_TIMEOUT}, _linear_regression::{LinearModel, LINEAR_MODEL_VERSION}, _neural_network::eval_network};
 
 
 // File masks
@@ -74,68 +74,178 @@ pub unsafe fn init_evaluation_masks() {
 }
 
 
-pub const MATERIAL_SCORE:[i32;12] = [
-    100, 300, 350, 500, 1000, 10000,
-    -100, -300, -350, -500, -1000, -10000
+// Every weight is given as [midgame, endgame]
+const MIDGAME:usize = 0;
+const ENDGAME:usize = 1;
+
+// Piece values (P, N, B, R, Q, K)
+pub const MATERIAL_SCORE:[[i32;6];2] = [
+    [100, 300, 350, 500, 1000, 0],
+    [120, 290, 320, 540,  980, 0]
 ];
 
-pub const PAWN_SCORE:[i32;64] = [
-    90,  90,  90,  90,  90,  90,  90,  90,
-    30,  30,  30,  40,  40,  30,  30,  30,
-    20,  20,  20,  30,  30,  30,  20,  20,
-    10,  10,  10,  20,  20,  10,  10,  10,
-     5,   5,  10,  20,  20,   5,   5,   5,
-     0,   0,   0,   5,   5,   0,   0,   0,
-     0,   0,   0, -10, -10,   0,   0,   0,
-     0,   0,   0,   0,   0,   0,   0,   0
+// Game phase contribution of each piece, 24 with all minor and major pieces on the board
+const PHASE_WEIGHT:[i32;6] = [0, 1, 1, 2, 4, 0];
+const TOTAL_PHASE:i32 = 24;
+
+// Piece square tables, from White's point of view
+pub const PAWN_SCORE:[[i32;64];2] = [
+    [
+        90,  90,  90,  90,  90,  90,  90,  90,
+        30,  30,  30,  40,  40,  30,  30,  30,
+        20,  20,  20,  30,  30,  30,  20,  20,
+        10,  10,  10,  20,  20,  10,  10,  10,
+         5,   5,  10,  20,  20,   5,   5,   5,
+         0,   0,   0,   5,   5,   0,   0,   0,
+         0,   0,   0, -10, -10,   0,   0,   0,
+         0,   0,   0,   0,   0,   0,   0,   0
+    ],
+    [
+         0,   0,   0,   0,   0,   0,   0,   0,
+        80,  80,  80,  80,  80,  80,  80,  80,
+        50,  50,  50,  50,  50,  50,  50,  50,
+        30,  30,  30,  30,  30,  30,  30,  30,
+        15,  15,  15,  15,  15,  15,  15,  15,
+         5,   5,   5,   5,   5,   5,   5,   5,
+         0,   0,   0,   0,   0,   0,   0,   0,
+         0,   0,   0,   0,   0,   0,   0,   0
+    ]
 ];
 
-pub const KNIGHT_SCORE:[i32;64] = [
-    -5,   0,   0,   0,   0,   0,   0,  -5,
-    -5,   0,   0,  10,  10,   0,   0,  -5,
-    -5,   5,  20,  20,  20,  20,   5,  -5,
-    -5,  10,  20,  30,  30,  20,  10,  -5,
-    -5,  10,  20,  30,  30,  20,  10,  -5,
-    -5,   5,  20,  10,  10,  20,   5,  -5,
-    -5,   0,   0,   0,   0,   0,   0,  -5,
-    -5, -10,   0,   0,   0,   0, -10,  -5
+pub const KNIGHT_SCORE:[[i32;64];2] = [
+    [
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+        -5,   0,   0,  10,  10,   0,   0,  -5,
+        -5,   5,  20,  20,  20,  20,   5,  -5,
+        -5,  10,  20,  30,  30,  20,  10,  -5,
+        -5,  10,  20,  30,  30,  20,  10,  -5,
+        -5,   5,  20,  10,  10,  20,   5,  -5,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+        -5, -10,   0,   0,   0,   0, -10,  -5
+    ],
+    [
+       -20, -10,  -5,  -5,  -5,  -5, -10, -20,
+       -10,  -5,   0,   5,   5,   0,  -5, -10,
+        -5,   0,  10,  15,  15,  10,   0,  -5,
+        -5,   5,  15,  20,  20,  15,   5,  -5,
+        -5,   5,  15,  20,  20,  15,   5,  -5,
+        -5,   0,  10,  15,  15,  10,   0,  -5,
+       -10,  -5,   0,   5,   5,   0,  -5, -10,
+       -20, -10,  -5,  -5,  -5,  -5, -10, -20
+    ]
 ];
 
-pub const BISHOP_SCORE:[i32;64] = [
-     0,   0,   0,   0,   0,   0,   0,   0,
-     0,   0,   0,   0,   0,   0,   0,   0,
-     0,   20,   0,  10,  10,   0,   20,   0,
-     0,   0,  10,  20,  20,  10,   0,   0,
-     0,   0,  10,  20,  20,  10,   0,   0,
-     0,  10,   0,   0,   0,   0,  10,   0,
-     0,  30,   0,   0,   0,   0,  30,   0,
-     0,   0, -10,   0,   0, -10,   0,   0
+pub const BISHOP_SCORE:[[i32;64];2] = [
+    [
+         0,   0,   0,   0,   0,   0,   0,   0,
+         0,   0,   0,   0,   0,   0,   0,   0,
+         0,  20,   0,  10,  10,   0,  20,   0,
+         0,   0,  10,  20,  20,  10,   0,   0,
+         0,   0,  10,  20,  20,  10,   0,   0,
+         0,  10,   0,   0,   0,   0,  10,   0,
+         0,  30,   0,   0,   0,   0,  30,   0,
+         0,   0, -10,   0,   0, -10,   0,   0
+    ],
+    [
+       -10,  -5,  -5,  -5,  -5,  -5,  -5, -10,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+        -5,   0,   5,  10,  10,   5,   0,  -5,
+        -5,   0,   5,  10,  10,   5,   0,  -5,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+       -10,  -5,  -5,  -5,  -5,  -5,  -5, -10
+    ]
+];
 
+pub const ROOK_SCORE:[[i32;64];2] = [
+    [
+        50,  50,  50,  50,  50,  50,  50,  50,
+        50,  50,  50,  50,  50,  50,  50,  50,
+         0,   0,  10,  20,  20,  10,   0,   0,
+         0,   0,  10,  20,  20,  10,   0,   0,
+         0,   0,  10,  20,  20,  10,   0,   0,
+         0,   0,  10,  20,  20,  10,   0,   0,
+         0,   0,  10,  20,  20,  10,   0,   0,
+         0,   0,   0,  20,  20,   0,   0,   0
+    ],
+    [
+        10,  10,  10,  10,  10,  10,  10,  10,
+        20,  20,  20,  20,  20,  20,  20,  20,
+         5,   5,   5,   5,   5,   5,   5,   5,
+         0,   0,   0,   0,   0,   0,   0,   0,
+         0,   0,   0,   0,   0,   0,   0,   0,
+         0,   0,   0,   0,   0,   0,   0,   0,
+         0,   0,   0,   0,   0,   0,   0,   0,
+         0,   0,   0,   0,   0,   0,   0,   0
+    ]
 ];
 
-pub const ROOK_SCORE:[i32;64] = [
-    50,  50,  50,  50,  50,  50,  50,  50,
-    50,  50,  50,  50,  50,  50,  50,  50,
-     0,   0,  10,  20,  20,  10,   0,   0,
-     0,   0,  10,  20,  20,  10,   0,   0,
-     0,   0,  10,  20,  20,  10,   0,   0,
-     0,   0,  10,  20,  20,  10,   0,   0,
-     0,   0,  10,  20,  20,  10,   0,   0,
-     0,   0,   0,  20,  20,   0,   0,   0
+pub const QUEEN_SCORE:[[i32;64];2] = [
+    [
+       -10,  -5,  -5,  -5,  -5,  -5,  -5, -10,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+       -10,  -5,  -5,   0,   0,  -5,  -5, -10
+    ],
+    [
+       -10,  -5,  -5,  -5,  -5,  -5,  -5, -10,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+        -5,   5,  10,  10,  10,  10,   5,  -5,
+        -5,   5,  10,  15,  15,  10,   5,  -5,
+        -5,   5,  10,  15,  15,  10,   5,  -5,
+        -5,   5,  10,  10,  10,  10,   5,  -5,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+       -10,  -5,  -5,  -5,  -5,  -5,  -5, -10
+    ]
+];
 
+// Midgame keeps the king tucked away behind its pawns, the endgame brings it to the centre
+pub const KING_SCORE:[[i32;64];2] = [
+    [
+       -30, -40, -40, -50, -50, -40, -40, -30,
+       -30, -40, -40, -50, -50, -40, -40, -30,
+       -30, -40, -40, -50, -50, -40, -40, -30,
+       -30, -40, -40, -50, -50, -40, -40, -30,
+       -20, -30, -30, -40, -40, -30, -30, -20,
+       -10, -20, -20, -20, -20, -20, -20, -10,
+         0,   0,  -5, -10, -10,  -5,   0,   0,
+         5,  10,  10, -10,   0, -10,  15,   5
+    ],
+    [
+       -50, -30, -30, -30, -30, -30, -30, -50,
+       -30, -10,   0,   0,   0,   0, -10, -30,
+       -30,   0,  20,  25,  25,  20,   0, -30,
+       -30,   0,  25,  35,  35,  25,   0, -30,
+       -30,   0,  25,  35,  35,  25,   0, -30,
+       -30,   0,  20,  25,  25,  20,   0, -30,
+       -30, -10,   0,   0,   0,   0, -10, -30,
+       -50, -30, -30, -30, -30, -30, -30, -50
+    ]
 ];
 
-pub const KING_SCORE:[i32;64] = [
-     0,   0,   0,   0,   0,   0,   0,   0,
-     0,   0,   5,   5,   5,   5,   0,   0,
-     0,   5,   5,  10,  10,   5,   5,   0,
-     0,   5,  10,  20,  20,  10,   5,   0,
-     0,   5,  10,  20,  20,  10,   5,   0,
-     0,   0,   5,  10,  10,   5,   0,   0,
-     0,   5,   5,  -5,  -5,   0,   5,   0,
-     0,   0,   5,   0, -15,   0,  10,   0
+const PIECE_SQUARE_SCORE:[&[[i32;64];2];6] = [
+    &PAWN_SCORE, &KNIGHT_SCORE, &BISHOP_SCORE, &ROOK_SCORE, &QUEEN_SCORE, &KING_SCORE
 ];
 
+// Midgame material and piece-square values as linear model coefficients (pawns, White positive)
+pub fn classical_linear_coefficients() -> Vec<f64> {
+    let mut coefficients = vec![0.0; FEATURE_COUNT];
+    for piece in 0..12 {
+        let sign = if piece < 6 {1.0} else {-1.0};
+        for square in 0..64 {
+            let table_square = if piece < 6 {square} else {MIRROR_SCORE[square]};
+            coefficients[piece*64 + square] = sign * PIECE_SQUARE_SCORE[piece % 6][MIDGAME][table_square] as f64 / 100.0;
+        }
+        coefficients[768 + piece] = sign * MATERIAL_SCORE[MIDGAME][piece % 6] as f64 / 100.0;
+    }
+    coefficients
+}
+
 const MIRROR_SCORE:[usize;64] = [
 	a1 as usize, b1 as usize, c1 as usize, d1 as usize, e1 as usize, f1 as usize, g1 as usize, h1 as usize,
 	a2 as usize, b2 as usize, c2 as usize, d2 as usize, e2 as usize, f2 as usize, g2 as usize, h2 as usize,
@@ -148,31 +258,25 @@ const MIRROR_SCORE:[usize;64] = [
 ];
 
 // PAWNS
-const DOUBLE_PAWN_PENALTY:i32 = -10;
-const ISOLATED_PAWN_PENALTY:i32 = -10;
-const PASSED_PAWN_BONUS:[i32;8] = [ 0, 5, 10, 20, 35, 60, 100, 200 ];
+const DOUBLE_PAWN_PENALTY:[i32;2] = [-10, -20];
+const ISOLATED_PAWN_PENALTY:[i32;2] = [-10, -15];
+const PASSED_PAWN_BONUS:[[i32;8];2] = [
+    [ 0, 5, 10, 20, 35, 60, 100, 200 ],
+    [ 0, 10, 20, 35, 60, 100, 160, 250 ]
+];
 
 // SLIDING PIECES
-const SEMI_OPEN_FILE_SCORE:i32 = 10;
-const OPEN_FILE_SCORE:i32 = 15;
+const SEMI_OPEN_FILE_SCORE:[i32;2] = [10, 5];
+const OPEN_FILE_SCORE:[i32;2] = [15, 10];
+const BISHOP_MOBILITY:[i32;2] = [1, 2];
+const QUEEN_MOBILITY:[i32;2] = [1, 2];
 
 // King
-const KING_SHIELD_BONUS:i32 = 5;
-
+const KING_SHIELD_BONUS:[i32;2] = [5, 0];
+const KING_OPEN_FILE_PENALTY:[i32;2] = [-15, 0];
 
-// For nn --------------------------------------------------
-// let mut stream = TcpStream::connect(server_address).unwrap();
-lazy_static::lazy_static! {
-    pub static ref STREAM: Mutex<TcpStream> = Mutex::new(
-        TcpStream::connect("127.0.0.1:5000").unwrap()
-    );
-}
-
-lazy_static::lazy_static! {
-    pub static ref NN_MODEL: Vec<Layer> = load();
-}
 
-pub static mut LINEAR_COEFF: LinearModel = LinearModel{ coefficients: vec![], intercept: 0.0 };
+pub static mut LINEAR_COEFF: LinearModel = LinearModel{ version: LINEAR_MODEL_VERSION, coefficients: vec![], intercept: 0.0 };
 // lazy_static::lazy_static! {
 //     pub static ref LINEAR_COEFF: LinearModel = {
 //         let json_data = std::fs::read_to_string("C:/Users/adtro/Uni/MatCAD/3r/APC/kaggle/final/linear_model.json").expect("Failed to read JSON file");
@@ -181,145 +285,260 @@ pub static mut LINEAR_COEFF: LinearModel = LinearModel{ coefficients: vec![], in
 // }
 
 
+/******************************************\
+ ==========================================
+                Evaluators
+ ==========================================
+\******************************************/
+
+// Evaluation backend, scores are in centipawns from the side to move's point of view
+pub trait Evaluator {
+    fn name(&self) -> &'static str;
+    unsafe fn evaluate(&self, board:&Board) -> i32;
+
+    // Several positions at once, backends that gain from batching override it
+    unsafe fn evaluate_batch(&self, boards:&[Board]) -> Vec<i32> {
+        boards.iter().map(|board| self.evaluate(board)).collect()
+    }
+}
+
+// Names accepted by the Evaluator option and the --eval argument
+pub const EVALUATOR_NAMES:[&str;5] = ["linear", "neural", "classical", "nnue", "external"];
+
+// Active evaluator, used by the search through evaluate()
+static mut EVALUATOR:Option<Box<dyn Evaluator>> = None;
+
+pub unsafe fn set_evaluator(name:&str) -> Result<(), String> {
+    let evaluator:Box<dyn Evaluator> = match name.to_lowercase().as_str() {
+        "linear" => {
+            if (*addr_of!(LINEAR_COEFF)).coefficients.is_empty() {
+                return Err("linear evaluator needs a coefficients file (uci -i)".to_string())
+            }
+            Box::new(LinearEvaluator)
+        },
+        "neural" => {
+            if eval_network().is_none() {
+                return Err("neural evaluator needs a network file (uci --eval-file or the EvalFile option)".to_string())
+            }
+            Box::new(NeuralEvaluator)
+        },
+        "classical" => Box::new(HandCraftedEvaluator),
+        "nnue" => {
+            if !network_loaded() {
+                return Err("nnue evaluator needs a network file (uci --nnue or the NnueFile option)".to_string())
+            }
+            Box::new(NnueEvaluator)
+        },
+        "external" => match &*addr_of!(EXTERNAL_ADDRESS) {
+            Some(address) => Box::new(ExternalEvaluator::new(address, EXTERNAL_TIMEOUT)),
+            None => return Err("external evaluator needs an address (uci --external or the ExternalEval option)".to_string()),
+        },
+        _ => return Err(format!("unknown evaluator {} (expected one of {})", name, EVALUATOR_NAMES.join(", "))),
+    };
+
+    EVALUATOR = Some(evaluator);
+    Ok(())
+}
+
+pub unsafe fn evaluator_name() -> &'static str {
+    match &*addr_of!(EVALUATOR) {
+        Some(evaluator) => evaluator.name(),
+        None => "none",
+    }
+}
+
 pub unsafe fn evaluate(board:&Board) -> i32 {
-    // let inputs = convert_board_to_csv(board);
-    // let mut stream = STREAM.lock().unwrap();
-    // let score = (communicate("127.0.0.1:5000", &inputs) * 100.0).round() as i32;
-    // return if board.side==Side::White {score} else {-score};
-    //-----------------------------------------------------
-    let raw_input: Vec<f64> = convert_board_to_csv(board).into_iter().map(|v| v as f64).collect();
-    // let input = Array1::from(raw_input);
-    // let score = (predict(input, &NN_MODEL)[0] * 100.0 )as i32;
-    // return if board.side==Side::White {score} else {-score};
-    //-----------------------------------------------------
-    let score = (_linear_regression::predict(&LINEAR_COEFF, &raw_input) * 100.0) as i32;
-    return if board.side==Side::White {score} else {-score};
-    //-----------------------------------------------------
-    let mut score = 0;
-    let mut bb:u64;
-    let mut piece:usize;
-    let mut square:usize;
+    match &*addr_of!(EVALUATOR) {
+        Some(evaluator) => evaluator.evaluate(board),
+        None => 0,
+    }
+}
 
-    for bb_piece in 0..12 {
-        let current_bb = board.bitboards[bb_piece];
-        bb = current_bb;
+pub unsafe fn evaluate_batch(boards:&[Board]) -> Vec<i32> {
+    match &*addr_of!(EVALUATOR) {
+        Some(evaluator) => evaluator.evaluate_batch(boards),
+        None => vec![0; boards.len()],
+    }
+}
 
-        piece = bb_piece;
-        while bb != 0 {
-            square = get_ls1b_index(bb);
+// Positions evaluated together by evaluate_fen_file
+const EVAL_BATCH_SIZE:usize = 256;
 
-            // Material score
-            score += MATERIAL_SCORE[piece];
+// Prints "score<TAB>fen" for every FEN of the file
+pub unsafe fn evaluate_fen_file(path:&str) -> Result<(), Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+    let fens:Vec<&str> = content.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).collect();
 
-            // Positional score
-            match bb_piece {
-                0 => {
-                    score += PAWN_SCORE[square];
+    for chunk in fens.chunks(EVAL_BATCH_SIZE) {
+        let boards:Vec<Board> = chunk.iter().map(|fen| Board::new_from_fen(fen)).collect();
+        for (score, fen) in evaluate_batch(&boards).iter().zip(chunk) {
+            println!("{}\t{}", score, fen);
+        }
+    }
 
-                    let doubled_pawns = count_bits(current_bb & FILE_MASKS[square]) - 1;
-                    if doubled_pawns != 0 {
-                        score += (doubled_pawns as i32) * DOUBLE_PAWN_PENALTY;
-                    }
+    Ok(())
+}
 
-                    if current_bb & ISOLATED_MASKS[square] == 0 {
-                        score += ISOLATED_PAWN_PENALTY;
-                    }
+// Linear model over the board features (coefficients loaded into LINEAR_COEFF),
+// read from the fixed point score make_move keeps up to date
+pub struct LinearEvaluator;
 
-                    if board.bitboards[6] & WHITE_PASSED_MASKS[square] == 0 {
-                        score += PASSED_PAWN_BONUS[GET_RANK[square]];
-                    }
-                },
-                1 => score += KNIGHT_SCORE[square],
-                2 => {
-                    score += BISHOP_SCORE[square];
+impl Evaluator for LinearEvaluator {
+    fn name(&self) -> &'static str { "linear" }
 
-                    score += count_bits(get_bishop_attacks(square, board.occupancies[2])) as i32;
-                },
-                3 => {
-                    score += ROOK_SCORE[square];
+    unsafe fn evaluate(&self, board:&Board) -> i32 {
+        let score = board.linear_score / (LINEAR_SCALE as i32 / 100);
+        if board.side==Side::White {score} else {-score}
+    }
+}
 
-                    if board.bitboards[0] & FILE_MASKS[square] == 0 {
-                        score += SEMI_OPEN_FILE_SCORE;
+// Multilayer perceptron over the same features (network loaded from EvalFile)
+pub struct NeuralEvaluator;
 
-                        if board.bitboards[6] & FILE_MASKS[square] == 0 {
-                            score += OPEN_FILE_SCORE;
-                        }
-                    }
-                },
-                4 => {
-                    score += count_bits(get_queen_attacks(square, board.occupancies[2])) as i32;
-                }
-                5 => {
-                    score += KING_SCORE[square];
+impl Evaluator for NeuralEvaluator {
+    fn name(&self) -> &'static str { "neural" }
 
-                    if board.bitboards[0] & FILE_MASKS[square] == 0 {
-                        score -= SEMI_OPEN_FILE_SCORE;
+    unsafe fn evaluate(&self, board:&Board) -> i32 {
+        let Some(network) = eval_network() else { return 0 };
+        let mut raw_input: Vec<f64> = convert_board_to_csv(board).into_iter().map(|v| v as f64).collect();
+        // Networks trained on the piece features only
+        raw_input.truncate(network.input_size());
+        let score = network.centipawns(network.predict(&raw_input));
+        if board.side==Side::White {score} else {-score}
+    }
 
-                        if board.bitboards[6] & FILE_MASKS[square] == 0 {
-                            score -= OPEN_FILE_SCORE;
-                        }
-                    }
+    unsafe fn evaluate_batch(&self, boards:&[Board]) -> Vec<i32> {
+        let Some(network) = eval_network() else { return vec![0; boards.len()] };
+        let mut inputs = Array2::zeros((boards.len(), network.input_size()));
+        for (mut row, board) in inputs.rows_mut().into_iter().zip(boards) {
+            for (input, feature) in row.iter_mut().zip(convert_board_to_csv(board)) {
+                *input = feature as f64;
+            }
+        }
 
-                    score += count_bits(KING_ATTACKS[square] & board.occupancies[2]) as i32 * KING_SHIELD_BONUS;
-                },
+        network.predict_batch(inputs).iter().zip(boards).map(|(&output, board)| {
+            let score = network.centipawns(output);
+            if board.side==Side::White {score} else {-score}
+        }).collect()
+    }
+}
 
-                6 => {
-                    score -= PAWN_SCORE[MIRROR_SCORE[square]];
+// Quantized piece-square network, its accumulator is kept up to date by make_move
+pub struct NnueEvaluator;
 
-                    let doubled_pawns = count_bits(current_bb & FILE_MASKS[square]);
-                    if doubled_pawns != 1 {
-                        score -= (doubled_pawns as i32) * DOUBLE_PAWN_PENALTY;
-                    }
+impl Evaluator for NnueEvaluator {
+    fn name(&self) -> &'static str { "nnue" }
+
+    unsafe fn evaluate(&self, board:&Board) -> i32 {
+        evaluate_nnue(board)
+    }
+}
+
+// Material, piece-square tables, pawn structure, open files, king shield and mobility
+pub struct HandCraftedEvaluator;
+
+impl Evaluator for HandCraftedEvaluator {
+    fn name(&self) -> &'static str { "classical" }
+
+    unsafe fn evaluate(&self, board:&Board) -> i32 {
+        evaluate_hand_crafted(board)
+    }
+}
+
+#[inline(always)]
+fn add_term(term:&mut [i32;2], weights:[i32;2], count:i32) {
+    term[MIDGAME] += weights[MIDGAME] * count;
+    term[ENDGAME] += weights[ENDGAME] * count;
+}
+
+unsafe fn evaluate_hand_crafted(board:&Board) -> i32 {
+    // Midgame and endgame scores from White's point of view
+    let mut score:[i32;2] = [0, 0];
+    let mut phase = 0;
+
+    for bb_piece in 0..12 {
+        let white = bb_piece < 6;
+        let piece = bb_piece % 6;
+        let (own_pawns, enemy_pawns) = if white {(board.bitboards[0], board.bitboards[6])} else {(board.bitboards[6], board.bitboards[0])};
+        let own_pieces = board.occupancies[if white {0} else {1}];
+
+        let mut bb = board.bitboards[bb_piece];
+        phase += count_bits(bb) as i32 * PHASE_WEIGHT[piece];
+
+        while bb != 0 {
+            let square = get_ls1b_index(bb);
+            let table_square = if white {square} else {MIRROR_SCORE[square]};
+
+            // Material and positional score
+            let mut term = [
+                MATERIAL_SCORE[MIDGAME][piece] + PIECE_SQUARE_SCORE[piece][MIDGAME][table_square],
+                MATERIAL_SCORE[ENDGAME][piece] + PIECE_SQUARE_SCORE[piece][ENDGAME][table_square]
+            ];
+
+            match piece {
+                0 => {
+                    let doubled_pawns = count_bits(own_pawns & FILE_MASKS[square]) as i32 - 1;
+                    add_term(&mut term, DOUBLE_PAWN_PENALTY, doubled_pawns);
 
-                    if current_bb & ISOLATED_MASKS[square] == 0 {
-                        score -= ISOLATED_PAWN_PENALTY;
+                    if own_pawns & ISOLATED_MASKS[square] == 0 {
+                        add_term(&mut term, ISOLATED_PAWN_PENALTY, 1);
                     }
 
-                    if board.bitboards[0] & BLACK_PASSED_MASKS[square] == 0 {
-                        score -= PASSED_PAWN_BONUS[7-GET_RANK[square]];
+                    let passed_mask = if white {WHITE_PASSED_MASKS[square]} else {BLACK_PASSED_MASKS[square]};
+                    if enemy_pawns & passed_mask == 0 {
+                        let rank = if white {GET_RANK[square]} else {7-GET_RANK[square]};
+                        term[MIDGAME] += PASSED_PAWN_BONUS[MIDGAME][rank];
+                        term[ENDGAME] += PASSED_PAWN_BONUS[ENDGAME][rank];
                     }
                 },
-                7 => score -= KNIGHT_SCORE[MIRROR_SCORE[square]],
-                8 => {
-                    score -= BISHOP_SCORE[MIRROR_SCORE[square]];
-
-                    score -= count_bits(get_bishop_attacks(square, board.occupancies[2])) as i32;
+                2 => {
+                    let mobility = count_bits(get_bishop_attacks(square, board.occupancies[2])) as i32;
+                    add_term(&mut term, BISHOP_MOBILITY, mobility);
                 },
-                9 => {
-                    score -= ROOK_SCORE[MIRROR_SCORE[square]];
-
-                    if board.bitboards[6] & FILE_MASKS[square] == 0 {
-                        score -= SEMI_OPEN_FILE_SCORE;
+                3 => {
+                    if own_pawns & FILE_MASKS[square] == 0 {
+                        add_term(&mut term, SEMI_OPEN_FILE_SCORE, 1);
 
-                        if board.bitboards[0] & FILE_MASKS[square] == 0 {
-                            score -= OPEN_FILE_SCORE;
+                        if enemy_pawns & FILE_MASKS[square] == 0 {
+                            add_term(&mut term, OPEN_FILE_SCORE, 1);
                         }
                     }
                 },
-                10 => {
-                    score -= count_bits(get_queen_attacks(square, board.occupancies[2])) as i32;
-                }
-                11 => {
-                    score -= KING_SCORE[MIRROR_SCORE[square]];
-
-                    if board.bitboards[6] & FILE_MASKS[square] == 0 {
-                        score += SEMI_OPEN_FILE_SCORE;
+                4 => {
+                    let mobility = count_bits(get_queen_attacks(square, board.occupancies[2])) as i32;
+                    add_term(&mut term, QUEEN_MOBILITY, mobility);
+                },
+                5 => {
+                    if own_pawns & FILE_MASKS[square] == 0 {
+                        add_term(&mut term, KING_OPEN_FILE_PENALTY, 1);
 
-                        if board.bitboards[0] & FILE_MASKS[square] == 0 {
-                            score += OPEN_FILE_SCORE;
+                        if enemy_pawns & FILE_MASKS[square] == 0 {
+                            add_term(&mut term, KING_OPEN_FILE_PENALTY, 1);
                         }
                     }
 
-                    score -= count_bits(KING_ATTACKS[square] & board.occupancies[2]) as i32 * KING_SHIELD_BONUS;
+                    let shield = count_bits(KING_ATTACKS[square] & own_pieces) as i32;
+                    add_term(&mut term, KING_SHIELD_BONUS, shield);
                 },
-
                 _ => ()
             }
 
+            if white {
+                score[MIDGAME] += term[MIDGAME];
+                score[ENDGAME] += term[ENDGAME];
+            } else {
+                score[MIDGAME] -= term[MIDGAME];
+                score[ENDGAME] -= term[ENDGAME];
+            }
+
             pop_bit!(bb, square);
         }
     }
 
+    // Interpolate between both phases, promoted pieces can push the phase past the opening value
+    let phase = phase.min(TOTAL_PHASE);
+    let score = (score[MIDGAME] * phase + score[ENDGAME] * (TOTAL_PHASE - phase)) / TOTAL_PHASE;
+
     if board.side==Side::White {score} else {-score}
 }
 
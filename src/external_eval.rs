@@ -0,0 +1,407 @@
+/******************************************\
+ ==========================================
+            External Evaluator
+ ==========================================
+\******************************************/
+
+/*
+    Evaluation by another program, reached over TCP ("host:port") or started as a
+    subprocess talking on its stdin/stdout ("exec:program arg1 arg2 ..."). The
+    connection is opened once and kept for the whole session.
+
+    Protocol, one message per line (UTF-8, "\n" terminated):
+
+        client: hello 1                     handshake, 1 is the protocol version
+        server: hello 1
+
+        client: eval <id> <n>               followed by n lines, one FEN each
+        server: result <id> <n>             followed by n lines, one score each
+        server: error <id> <message>        instead of a result
+
+        client: quit                        the server closes the connection
+
+    Scores are centipawns from White's point of view (decimals are rounded). Requests
+    carry an increasing id so answers arriving after a timeout are skipped. When the
+    server cannot be reached, answers too late or breaks the protocol, the classical
+    evaluation is used until a reconnection succeeds.
+
+    "chess eval-server" is a stand-in server speaking this protocol with the built-in
+    evaluators, to test the client without the real model.
+*/
+
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{bitboard::Board, eval::{evaluate_batch, Evaluator, HandCraftedEvaluator}, _csv_fen_to_bitboard::is_valid_fen, Side};
+
+pub const PROTOCOL_VERSION:u32 = 1;
+
+// Address given by --external or the ExternalEval option
+pub static mut EXTERNAL_ADDRESS:Option<String> = None;
+// Time allowed for an answer, in milliseconds
+pub static mut EXTERNAL_TIMEOUT:u64 = 1000;
+
+// Time before connecting again after a failure
+const RETRY_DELAY:Duration = Duration::from_secs(1);
+
+struct Connection {
+    writer:Box<dyn Write>,
+    lines:Receiver<String>,
+    child:Option<Child>,
+    next_id:u64,
+}
+
+impl Connection {
+    fn open(address:&str, timeout:Duration) -> Result<Connection, Box<dyn Error>> {
+        let (writer, reader, child):(Box<dyn Write>, Box<dyn BufRead + Send>, Option<Child>) = match address.strip_prefix("exec:") {
+            Some(command) => {
+                let mut parts = command.split_whitespace();
+                let program = parts.next().ok_or("empty command")?;
+                let mut child = Command::new(program)
+                    .args(parts)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+                let stdin = child.stdin.take().ok_or("no stdin")?;
+                let stdout = child.stdout.take().ok_or("no stdout")?;
+                (Box::new(BufWriter::new(stdin)), Box::new(BufReader::new(stdout)), Some(child))
+            },
+            None => {
+                let socket = address.to_socket_addrs()?.next().ok_or("address did not resolve")?;
+                let stream = TcpStream::connect_timeout(&socket, timeout)?;
+                stream.set_nodelay(true)?;
+                stream.set_write_timeout(Some(timeout))?;
+                let reader = BufReader::new(stream.try_clone()?);
+                (Box::new(BufWriter::new(stream)), Box::new(reader), None)
+            },
+        };
+
+        // Lines are read on their own thread so every wait can time out
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let mut connection = Connection { writer, lines, child, next_id: 1 };
+        writeln!(connection.writer, "hello {}", PROTOCOL_VERSION)?;
+        connection.writer.flush()?;
+
+        let answer = connection.read_line(Instant::now() + timeout)?;
+        if answer.trim() != format!("hello {}", PROTOCOL_VERSION) {
+            return Err(format!("unexpected handshake answer \"{}\"", answer.trim()).into())
+        }
+
+        Ok(connection)
+    }
+
+    fn read_line(&self, deadline:Instant) -> Result<String, Box<dyn Error>> {
+        match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err(Box::new(io::Error::new(io::ErrorKind::TimedOut, "timed out"))),
+            Err(RecvTimeoutError::Disconnected) => Err("connection closed".into()),
+        }
+    }
+
+    // White relative scores of the FENs, in the same order
+    fn evaluate(&mut self, fens:&[String], timeout:Duration) -> Result<Vec<i32>, Box<dyn Error>> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        writeln!(self.writer, "eval {} {}", id, fens.len())?;
+        for fen in fens {
+            writeln!(self.writer, "{}", fen)?;
+        }
+        self.writer.flush()?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let header = self.read_line(deadline)?;
+            let mut parts = header.split_whitespace();
+            let kind = parts.next().unwrap_or("");
+            let answer_id:u64 = parts.next().and_then(|id| id.parse().ok()).ok_or_else(|| format!("malformed answer \"{}\"", header))?;
+
+            match kind {
+                "result" => {
+                    let count:usize = parts.next().and_then(|n| n.parse().ok()).ok_or_else(|| format!("malformed answer \"{}\"", header))?;
+                    let mut scores = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        let line = self.read_line(deadline)?;
+                        let score:f64 = line.trim().parse().map_err(|_| format!("malformed score \"{}\"", line.trim()))?;
+                        scores.push(score.round() as i32);
+                    }
+
+                    // Answer to a request that already timed out
+                    if answer_id != id { continue; }
+
+                    if count != fens.len() {
+                        return Err(format!("{} scores for {} positions", count, fens.len()).into())
+                    }
+                    return Ok(scores)
+                },
+                "error" if answer_id == id => return Err(format!("server error: {}", parts.collect::<Vec<_>>().join(" ")).into()),
+                "error" => continue,
+                _ => return Err(format!("malformed answer \"{}\"", header).into()),
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = writeln!(self.writer, "quit");
+        let _ = self.writer.flush();
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn is_timeout(error:&(dyn Error + 'static)) -> bool {
+    error.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::TimedOut)
+}
+
+pub struct ExternalEvaluator {
+    address:String,
+    timeout:Duration,
+    connection:RefCell<Option<Connection>>,
+    retry_at:Cell<Option<Instant>>,
+    fallback:HandCraftedEvaluator,
+}
+
+impl ExternalEvaluator {
+    pub fn new(address:&str, timeout_ms:u64) -> ExternalEvaluator {
+        ExternalEvaluator {
+            address: address.to_string(),
+            timeout: Duration::from_millis(timeout_ms),
+            connection: RefCell::new(None),
+            retry_at: Cell::new(None),
+            fallback: HandCraftedEvaluator,
+        }
+    }
+
+    // Scores from the server, None when the fallback has to be used
+    fn request(&self, boards:&[Board]) -> Option<Vec<i32>> {
+        // Recent failure, use the fallback for a while
+        if self.retry_at.get().is_some_and(|retry_at| Instant::now() < retry_at) {
+            return None
+        }
+
+        let mut connection = self.connection.borrow_mut();
+        if connection.is_none() {
+            match Connection::open(&self.address, self.timeout) {
+                Ok(opened) => *connection = Some(opened),
+                Err(e) => {
+                    self.fail(&format!("cannot connect to {}: {}", self.address, e));
+                    return None
+                }
+            }
+        }
+
+        let fens:Vec<String> = boards.iter().map(|board| board.to_fen()).collect();
+        match connection.as_mut()?.evaluate(&fens, self.timeout) {
+            Ok(scores) => Some(scores),
+            Err(e) => {
+                // A late answer is skipped by its id, other errors leave the stream unusable
+                if !is_timeout(e.as_ref()) {
+                    *connection = None;
+                }
+                self.fail(&e.to_string());
+                None
+            }
+        }
+    }
+
+    fn fail(&self, message:&str) {
+        println!("info string external evaluator: {}, using the classical evaluation", message);
+        self.retry_at.set(Some(Instant::now() + RETRY_DELAY));
+    }
+}
+
+impl Evaluator for ExternalEvaluator {
+    fn name(&self) -> &'static str { "external" }
+
+    unsafe fn evaluate(&self, board:&Board) -> i32 {
+        match self.request(std::slice::from_ref(board)) {
+            Some(scores) => if board.side==Side::White {scores[0]} else {-scores[0]},
+            None => self.fallback.evaluate(board),
+        }
+    }
+
+    unsafe fn evaluate_batch(&self, boards:&[Board]) -> Vec<i32> {
+        match self.request(boards) {
+            Some(scores) => scores.iter().zip(boards).map(|(&score, board)| if board.side==Side::White {score} else {-score}).collect(),
+            None => boards.iter().map(|board| self.fallback.evaluate(board)).collect(),
+        }
+    }
+}
+
+/******************************************\
+ ==========================================
+              Stand-in Server
+ ==========================================
+\******************************************/
+
+// Answers the protocol on one connection with the active evaluator
+unsafe fn serve(reader:impl BufRead, mut writer:impl Write, delay:Duration) -> io::Result<()> {
+    let mut lines = reader.lines();
+
+    while let Some(line) = lines.next() {
+        let line = line?;
+        let parts:Vec<&str> = line.split_whitespace().collect();
+
+        match parts.as_slice() {
+            ["hello", _] => writeln!(writer, "hello {}", PROTOCOL_VERSION)?,
+            ["eval", id, n] => {
+                let count:usize = n.parse().unwrap_or(0);
+                let mut fens = Vec::with_capacity(count);
+                for _ in 0..count {
+                    match lines.next() {
+                        Some(fen) => fens.push(fen?.trim().to_string()),
+                        None => return Ok(()),
+                    }
+                }
+
+                // Board::new_from_fen panics on malformed input
+                if let Some(fen) = fens.iter().find(|fen| !is_valid_fen(fen)) {
+                    writeln!(writer, "error {} invalid fen {}", id, fen)?;
+                } else {
+                    let boards:Vec<Board> = fens.iter().map(|fen| Board::new_from_fen(fen)).collect();
+                    thread::sleep(delay);
+
+                    writeln!(writer, "result {} {}", id, boards.len())?;
+                    for (score, board) in evaluate_batch(&boards).iter().zip(boards.iter()) {
+                        writeln!(writer, "{}", if board.side==Side::White {*score} else {-score})?;
+                    }
+                }
+            },
+            ["quit"] => return Ok(()),
+            [] => continue,
+            _ => writeln!(writer, "error 0 unknown command {}", parts[0])?,
+        }
+
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+// Serves on stdin/stdout without an address, otherwise one TCP client at a time
+pub unsafe fn run_eval_server(listen:Option<&str>, delay_ms:u64) -> io::Result<()> {
+    let delay = Duration::from_millis(delay_ms);
+
+    match listen {
+        None => serve(io::stdin().lock(), io::stdout().lock(), delay),
+        Some(address) => {
+            let listener = TcpListener::bind(address)?;
+            eprintln!("Listening on {}", listener.local_addr()?);
+            for stream in listener.incoming() {
+                let stream = stream?;
+                stream.set_nodelay(true)?;
+                if let Err(e) = serve(BufReader::new(stream.try_clone()?), BufWriter::new(stream), delay) {
+                    eprintln!("Connection closed: {}", e);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Once;
+    use crate::eval::set_evaluator;
+
+    const FENS:[&str;3] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+        "4k3/8/8/8/8/8/8/3QK3 w - - 0 1",
+    ];
+
+    fn init() {
+        static INIT:Once = Once::new();
+        INIT.call_once(|| unsafe {
+            crate::init_all_vars();
+            set_evaluator("classical").unwrap();
+        });
+    }
+
+    // Stand-in server on a loopback port, answering after delay
+    fn start_server(delay:Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let stream = listener.incoming().next().unwrap().unwrap();
+            unsafe { serve(BufReader::new(stream.try_clone().unwrap()), BufWriter::new(stream), delay).unwrap(); }
+        });
+        address
+    }
+
+    fn white_scores(fens:&[&str]) -> Vec<i32> {
+        fens.iter().map(|fen| {
+            let board = Board::new_from_fen(fen);
+            let score = unsafe { HandCraftedEvaluator.evaluate(&board) };
+            if board.side==Side::White {score} else {-score}
+        }).collect()
+    }
+
+    #[test]
+    fn batch_answers_keep_the_request_order() {
+        init();
+        let evaluator = ExternalEvaluator::new(&start_server(Duration::ZERO), 5000);
+        let boards:Vec<Board> = FENS.iter().map(|fen| Board::new_from_fen(fen)).collect();
+        let expected:Vec<i32> = boards.iter().map(|board| unsafe { HandCraftedEvaluator.evaluate(board) }).collect();
+
+        assert_eq!(unsafe { evaluator.evaluate_batch(&boards) }, expected);
+        assert!(evaluator.retry_at.get().is_none());
+    }
+
+    #[test]
+    fn late_answers_are_skipped_by_id() {
+        init();
+        let mut connection = Connection::open(&start_server(Duration::from_millis(300)), Duration::from_secs(5)).unwrap();
+
+        let first = vec![FENS[0].to_string(), FENS[1].to_string()];
+        let error = connection.evaluate(&first, Duration::from_millis(50)).unwrap_err();
+        assert!(is_timeout(error.as_ref()));
+
+        // The answer to the first request arrives first and is dropped
+        let second = vec![FENS[2].to_string()];
+        assert_eq!(connection.evaluate(&second, Duration::from_secs(5)).unwrap(), white_scores(&FENS[2..]));
+    }
+
+    #[test]
+    fn invalid_fens_get_an_error_answer() {
+        init();
+        let mut connection = Connection::open(&start_server(Duration::ZERO), Duration::from_secs(5)).unwrap();
+
+        let error = connection.evaluate(&["not a fen".to_string()], Duration::from_secs(5)).unwrap_err();
+        assert!(error.to_string().contains("invalid fen"));
+        // The server is still answering
+        assert_eq!(connection.evaluate(&[FENS[0].to_string()], Duration::from_secs(5)).unwrap(), white_scores(&FENS[..1]));
+    }
+
+    #[test]
+    fn unreachable_server_falls_back_to_the_classical_evaluation() {
+        init();
+        // Bound then dropped, so nothing listens on the port
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let evaluator = ExternalEvaluator::new(&address, 200);
+        let board = Board::new_from_fen(FENS[1]);
+
+        assert_eq!(unsafe { evaluator.evaluate(&board) }, unsafe { HandCraftedEvaluator.evaluate(&board) });
+        assert!(evaluator.retry_at.get().is_some());
+    }
+}
@@ -0,0 +1,84 @@
+/******************************************\
+ ==========================================
+          Incremental Linear Score
+ ==========================================
+\******************************************/
+
+use std::ptr::{addr_of, addr_of_mut};
+
+use crate::{bitboard::{Board, get_ls1b_index}, get_bit, pop_bit, Side, _csv_fen_to_bitboard::PIECE_FEATURES, _linear_regression::LinearModel};
+
+// Fixed point units per pawn, the score divided by 100 gives centipawns
+pub const LINEAR_SCALE:f64 = 10000.0;
+
+// Piece-square bit plus piece count coefficient of every piece on every square
+pub static mut LINEAR_PIECE_SCORES:[[i32;64];12] = [[0;64];12];
+// Indexed by en passant square, the model only looks at its file
+pub static mut LINEAR_ENPASSANT_SCORES:[i32;64] = [0;64];
+pub static mut LINEAR_CASTLE_SCORES:[i32;16] = [0;16];
+// Added while White is to move
+pub static mut LINEAR_SIDE_SCORE:i32 = 0;
+pub static mut LINEAR_INTERCEPT:i32 = 0;
+
+fn to_fixed(value:f64) -> i32 {
+    (value * LINEAR_SCALE).round() as i32
+}
+
+// Features missing from older models weigh nothing
+pub unsafe fn init_linear_scores(model:&LinearModel) {
+    let coefficient = |index:usize| *model.coefficients.get(index).unwrap_or(&0.0);
+
+    for (piece, scores) in (*addr_of_mut!(LINEAR_PIECE_SCORES)).iter_mut().enumerate() {
+        for (square, score) in scores.iter_mut().enumerate() {
+            *score = to_fixed(coefficient(piece*64 + square) + coefficient(768 + piece));
+        }
+    }
+
+    LINEAR_SIDE_SCORE = to_fixed(coefficient(PIECE_FEATURES));
+
+    for (index, castle_score) in (*addr_of_mut!(LINEAR_CASTLE_SCORES)).iter_mut().enumerate() {
+        let mut score = 0.0;
+        for right in 0..4 {
+            if index & (1 << right) != 0 {
+                score += coefficient(PIECE_FEATURES + 1 + right);
+            }
+        }
+        *castle_score = to_fixed(score);
+    }
+
+    for (square, score) in (*addr_of_mut!(LINEAR_ENPASSANT_SCORES)).iter_mut().enumerate() {
+        *score = to_fixed(coefficient(PIECE_FEATURES + 5 + square % 8));
+    }
+
+    LINEAR_INTERCEPT = to_fixed(model.intercept);
+}
+
+// Full recomputation of the score kept up to date by make_move
+pub unsafe fn generate_linear_score(board:&Board) -> i32 {
+    let mut score = LINEAR_INTERCEPT;
+    let mut bb:u64;
+
+    for (&bitboard, scores) in board.bitboards.iter().zip((*addr_of!(LINEAR_PIECE_SCORES)).iter()) {
+        bb = bitboard;
+
+        while bb != 0 {
+            let square = get_ls1b_index(bb);
+
+            score += scores[square];
+
+            pop_bit!(bb, square);
+        }
+    }
+
+    if let Some(square) = board.en_passant {
+        score += LINEAR_ENPASSANT_SCORES[square];
+    }
+
+    score += LINEAR_CASTLE_SCORES[board.castle as usize];
+
+    if board.side == Side::White {
+        score += LINEAR_SIDE_SCORE;
+    }
+
+    score
+}
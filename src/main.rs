@@ -6,13 +6,12 @@ use std::{fs::File, io::BufReader, net::TcpStream, vec};
 
 #[allow(dead_code)]
 use _csv_fen_to_bitboard::convert_board_to_csv;
-use _csv_fen_to_bitboard::{play_random_game, process_csv};
-use _linear_regression::{parse_csv, LinearModel};
-use _neural_network::{close_connection, communicate, load, open_program, send_and_receive};
+use _csv_fen_to_bitboard::{play_random_game, process_csv, OutputFormat};
+use _linear_regression::{parse_csv, train_linear, LinearModel, Loss, TrainOptions};
 use attacks::{get_bishop_attacks, get_rook_attacks, get_queen_attacks, PAWN_ATTACKS};
 use bitboard::Board;
 use clap::Parser;
-use clap_derive::{Parser, Subcommand};
+use clap_derive::{Args, Parser, Subcommand};
 use eval::*;
 use hashing::{init_random_hash_keys, generate_hash_key};
 use linfa::traits::Fit;
@@ -26,11 +25,19 @@ use nalgebra::DVector;
 use ndarray::Array;
 use ndarray::Array2;
 use ndarray::{Array1, ArrayBase, OwnedRepr};
-use perft::{perft_driver, perft_test};
+use perft::{perft_driver, perft_test, perft_parallel_test, run_perft_suite};
 use polars::{io::SerReader, prelude::{DataType, Float64Type, IndexOrder}};
 use search::PV_TABLE;
 use serde::Deserialize;
 use serde::Serialize;
+use texel::{texel_tune, TexelOptions};
+use datagen::{generate_data, DatagenOptions};
+use quiet_filter::filter_quiet;
+use engine_match::{run_match, MatchOptions, TimeControl};
+use epd::run_epd_suite;
+use bench::{run_bench, BENCH_DEPTH};
+use endgame::generate_tables;
+use book::{build_book, BookOptions};
 use transposition::{write_hash_entry, read_hash_entry};
 // use tweak::{init_eval_constants, EngineValues, save_to_json_file};
 use uci::{parse_move, parse_go, parse_position, uci_loop, search_position};
@@ -51,6 +58,20 @@ mod uci;
 mod hashing;
 mod random_numbers;
 mod perft;
+mod see;
+mod linear_score;
+mod nnue;
+mod external_eval;
+mod texel;
+mod datagen;
+mod quiet_filter;
+mod uci_client;
+mod engine_match;
+mod epd;
+mod bench;
+mod syzygy;
+mod endgame;
+mod book;
 
 
 #[allow(non_camel_case_types)]
@@ -106,9 +127,11 @@ fn init_all_vars() {
         attacks::init_leapers_attacks();
         attacks::init_sliders_attacks(true);
         attacks::init_sliders_attacks(false);   
+        attacks::init_line_masks();
         // random_numbers::init_magic_numbers();
         init_random_hash_keys();
         init_evaluation_masks();
+        linear_score::init_linear_scores(&*std::ptr::addr_of!(LINEAR_COEFF));
 
         // init_eval_constants("./data.json");
         // let val = EngineValues { material_score: [100,300,300,500,900,20000] };
@@ -142,24 +165,378 @@ struct Cli {
 enum Commands {
     /// Process a CSV file
     ProcessCsv {
-        /// Input file
+        /// Input file ("fen,evaluation" CSV, plain or gzip compressed)
         #[arg(short, long)]
         input: String,
 
         /// Output file
         #[arg(short, long)]
         output: String,
+
+        /// Output format: csv or binary (binary for .bin outputs by default)
+        #[arg(short, long)]
+        format: Option<OutputFormat>,
+
+        /// Conversion threads (all cores by default)
+        #[arg(short, long)]
+        threads: Option<usize>,
     },
     /// Run the UCI command
     Uci {
+        #[command(flatten)]
+        evaluator: EvaluatorArgs,
+    },
+    /// Evaluate every FEN of a file (one per line), in centipawns for the side to move
+    Eval {
+        /// File with one FEN per line
         #[arg(short, long)]
-        input: String,
+        fens: String,
+
+        #[command(flatten)]
+        evaluator: EvaluatorArgs,
+    },
+    /// Stand-in external evaluation server using the built-in evaluators
+    EvalServer {
+        /// Address to listen on, stdin/stdout when missing
+        #[arg(short, long)]
+        listen: Option<String>,
+
+        /// Milliseconds to wait before every answer (to test client timeouts)
+        #[arg(long, default_value_t = 0)]
+        delay: u64,
+
+        #[command(flatten)]
+        evaluator: EvaluatorArgs,
     },
     /// Train Model
     LinearRegression {
         /// Input file
         #[arg(short, long)]
         input: String,
+    },
+    /// Fit a linear model on the CSV written by process-csv
+    TrainLinear {
+        /// Input CSV (features followed by the score)
+        #[arg(short, long)]
+        input: String,
+
+        /// Output model (JSON, as read by uci -i)
+        #[arg(short, long)]
+        output: String,
+
+        /// Loss: squared, huber or logistic
+        #[arg(short, long, default_value = "squared")]
+        loss: String,
+
+        /// L2 penalty on the coefficients (0 for ordinary least squares)
+        #[arg(long, default_value_t = 0.0)]
+        ridge: f64,
+
+        /// Huber threshold in pawns
+        #[arg(long, default_value_t = 1.0)]
+        delta: f64,
+
+        /// Logistic scale in pawns, win probability is sigmoid(score / scale)
+        #[arg(long, default_value_t = 4.0)]
+        sigmoid_scale: f64,
+
+        /// Fraction of the positions kept for validation
+        #[arg(long, default_value_t = 0.1)]
+        validation: f64,
+
+        /// Iterations of the huber and logistic fits
+        #[arg(long, default_value_t = 10)]
+        iterations: usize,
+
+        /// Multiplier turning the CSV scores into pawns (1.0 when they already are pawns)
+        #[arg(long, default_value_t = 0.01)]
+        target_scale: f64,
+    },
+    /// Tune the linear model on game results (Texel tuning)
+    Texel {
+        /// Quiet positions, a FEN followed by the result: 1-0, 0-1, 1/2-1/2 or [1.0], [0.5], [0.0]
+        #[arg(short, long)]
+        input: String,
+
+        /// Output model (JSON, as read by uci -i)
+        #[arg(short, long)]
+        output: String,
+
+        /// Starting coefficients: classical, zero or a linear model file
+        #[arg(long, default_value = "classical")]
+        init: String,
+
+        /// Sigmoid scale per pawn (fitted on the starting coefficients if missing)
+        #[arg(short, long)]
+        k: Option<f64>,
+
+        /// Passes of gradient descent over the positions
+        #[arg(short, long, default_value_t = 200)]
+        epochs: usize,
+
+        /// Adam step size, in pawns
+        #[arg(long, default_value_t = 0.002)]
+        learning_rate: f64,
+
+        /// Fraction of the positions kept for validation
+        #[arg(long, default_value_t = 0.1)]
+        validation: f64,
+    },
+    /// Drop positions whose static evaluation differs from the quiescence search
+    FilterQuiet {
+        /// Input file ("fen,evaluation" CSV, plain or gzip compressed)
+        #[arg(short, long)]
+        input: String,
+
+        /// Output CSV, with the columns of the input
+        #[arg(short, long)]
+        output: String,
+
+        /// Largest difference (centipawns) between static and quiescence scores
+        #[arg(short, long, default_value_t = 60)]
+        margin: i32,
+
+        /// Replace tactical positions with the quiet end of their capture sequence
+        #[arg(long)]
+        leaf: bool,
+
+        #[command(flatten)]
+        evaluator: EvaluatorArgs,
+    },
+    /// Generate training positions from self-play games
+    Datagen {
+        /// Output CSV (fen,evaluation,result)
+        #[arg(short, long)]
+        output: String,
+
+        /// Number of games
+        #[arg(short, long, default_value_t = 100)]
+        games: usize,
+
+        /// Search depth per move
+        #[arg(short, long, default_value_t = 6)]
+        depth: i32,
+
+        /// Node budget per move (on top of the depth limit)
+        #[arg(short, long)]
+        nodes: Option<u32>,
+
+        /// Random moves played at the start of each game
+        #[arg(long, default_value_t = 8)]
+        random_plies: usize,
+
+        /// Games longer than this are drawn
+        #[arg(long, default_value_t = 400)]
+        max_plies: usize,
+
+        /// Score (centipawns) ending the game once held for a few plies
+        #[arg(long, default_value_t = 1500)]
+        adjudicate: i32,
+
+        /// Seed of the random openings
+        #[arg(long)]
+        seed: Option<u64>,
+
+        #[command(flatten)]
+        evaluator: EvaluatorArgs,
+    },
+    /// Play two engines against each other and report Elo with an SPRT
+    Match {
+        /// First engine: a linear coefficients file, or "exec:command" for any UCI engine
+        #[arg(long)]
+        engine1: String,
+
+        /// Second engine, same forms as the first
+        #[arg(long)]
+        engine2: String,
+
+        /// Maximum number of games (stops earlier once the SPRT decides)
+        #[arg(short, long, default_value_t = 1000)]
+        games: usize,
+
+        /// Time control in seconds, base+increment
+        #[arg(long, default_value = "10+0.1")]
+        tc: TimeControl,
+
+        /// Fixed search depth instead of the time control
+        #[arg(short, long)]
+        depth: Option<u32>,
+
+        /// Opening positions, one FEN or EPD per line (random openings if missing)
+        #[arg(short, long)]
+        openings: Option<String>,
+
+        /// Length of the random openings
+        #[arg(long, default_value_t = 8)]
+        random_plies: usize,
+
+        /// Games longer than this are drawn
+        #[arg(long, default_value_t = 400)]
+        max_plies: usize,
+
+        /// SPRT null hypothesis, Elo of engine 1 over engine 2
+        #[arg(long, default_value_t = 0.0)]
+        elo0: f64,
+
+        /// SPRT alternative hypothesis
+        #[arg(long, default_value_t = 5.0)]
+        elo1: f64,
+
+        /// SPRT false positive rate
+        #[arg(long, default_value_t = 0.05)]
+        alpha: f64,
+
+        /// SPRT false negative rate
+        #[arg(long, default_value_t = 0.05)]
+        beta: f64,
+
+        /// Seed of the random openings
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Run an EPD test suite (bm/am/id), such as WAC or STS
+    Epd {
+        /// EPD file
+        #[arg(short, long)]
+        input: String,
+
+        /// Search depth per position
+        #[arg(short, long)]
+        depth: Option<i32>,
+
+        /// Search time per position in milliseconds (1000 when no depth is given)
+        #[arg(short, long)]
+        movetime: Option<u64>,
+
+        #[command(flatten)]
+        evaluator: EvaluatorArgs,
+    },
+    /// Search the built-in bench positions, printing the node signature and nps
+    Bench {
+        /// Search depth
+        #[arg(short, long, default_value_t = BENCH_DEPTH)]
+        depth: i32,
+
+        #[command(flatten)]
+        evaluator: EvaluatorArgs,
+    },
+    /// Build distance-to-mate endgame tables by retrograde analysis
+    EndgameGen {
+        /// Materials to generate, comma separated (KQvK,KRvK,KPvK,KBNvK), up to 4 pieces
+        #[arg(short, long, value_delimiter = ',')]
+        materials: Vec<String>,
+
+        /// Output directory, the tables already there are reused
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Build a Polyglot opening book from PGN games
+    Book {
+        /// PGN file
+        #[arg(short, long)]
+        input: String,
+
+        /// Output book (.bin)
+        #[arg(short, long)]
+        output: String,
+
+        /// Plies of every game added to the book
+        #[arg(long, default_value_t = 20)]
+        max_plies: usize,
+
+        /// Games a move must appear in to be kept
+        #[arg(long, default_value_t = 1)]
+        min_games: u32,
+
+        /// Weight of a win for the side that played the move
+        #[arg(long, default_value_t = 2)]
+        win_weight: u32,
+
+        /// Weight of a draw
+        #[arg(long, default_value_t = 1)]
+        draw_weight: u32,
+
+        /// Weight of a loss
+        #[arg(long, default_value_t = 0)]
+        loss_weight: u32,
+    },
+    /// Run the perft suite, or divide a single position
+    Perft {
+        /// EPD file with ";D<depth> <nodes>" entries (built-in suite if missing)
+        #[arg(short, long)]
+        epd: Option<String>,
+
+        /// FEN to divide instead of running a suite
+        #[arg(short, long)]
+        fen: Option<String>,
+
+        /// Maximum depth
+        #[arg(short, long, default_value_t = 6)]
+        depth: isize,
+
+        /// Threads splitting the root moves (parallel hashed perft when > 1)
+        #[arg(short, long, default_value_t = 1)]
+        threads: usize,
+
+        /// Perft cache size in MB (parallel hashed perft when > 0)
+        #[arg(long, default_value_t = 0)]
+        hash: usize,
+    }
+}
+
+#[derive(Args)]
+struct EvaluatorArgs {
+    /// Linear model coefficients (JSON)
+    #[arg(short, long)]
+    input: Option<String>,
+
+    /// Evaluator: linear, neural, classical or nnue
+    #[arg(short, long, default_value = "linear")]
+    eval: String,
+
+    /// NNUE network file
+    #[arg(long)]
+    nnue: Option<String>,
+
+    /// Neural network file (JSON) for the neural evaluator
+    #[arg(long)]
+    eval_file: Option<String>,
+
+    /// External evaluator address: "host:port" or "exec:command args"
+    #[arg(long)]
+    external: Option<String>,
+
+    /// Milliseconds to wait for the external evaluator
+    #[arg(long, default_value_t = 1000)]
+    external_timeout: u64,
+}
+
+// Loads the evaluation files and selects the evaluator, exits on failure
+unsafe fn init_evaluator(args:&EvaluatorArgs) {
+    if let Some(input) = &args.input {
+        LINEAR_COEFF = LinearModel::load(input).unwrap_or_else(|e| {
+            eprintln!("Failed to load linear model {}: {}", input, e);
+            std::process::exit(1);
+        });
+    }
+    init_all_vars();
+    if let Some(nnue) = &args.nnue {
+        if let Err(e) = nnue::load_network(nnue) {
+            eprintln!("Failed to load NNUE network {}: {}", nnue, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(eval_file) = &args.eval_file {
+        if let Err(e) = _neural_network::load_eval_file(eval_file) {
+            eprintln!("Failed to load network {}: {}", eval_file, e);
+            std::process::exit(1);
+        }
+    }
+    external_eval::EXTERNAL_ADDRESS = args.external.clone();
+    external_eval::EXTERNAL_TIMEOUT = args.external_timeout;
+    if let Err(e) = set_evaluator(&args.eval) {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
 
@@ -169,49 +546,134 @@ fn main() {
         let cli = Cli::parse();
 
         match cli.command {
-            Commands::ProcessCsv { input, output } => {
-                process_csv(
+            Commands::ProcessCsv { input, output, format, threads } => {
+                let format = format.unwrap_or_else(|| OutputFormat::from_path(&output));
+                let threads = threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+                if let Err(e) = process_csv(
                     &input, // "C:/Users/adtro/Uni/MatCAD/3r/APC/kaggle/final/dataset_.csv"
                     &output, // "C:/Users/adtro/Uni/MatCAD/3r/APC/kaggle/final/final_data.csv"
-                ).unwrap();
+                    format,
+                    threads,
+                ) {
+                    eprintln!("Processing failed: {}", e);
+                    std::process::exit(1);
+                }
             }
-            Commands::Uci { input } => {
-                let json_data = std::fs::read_to_string(&input).expect("Failed to read JSON file");
-                LINEAR_COEFF = serde_json::from_str(&json_data).expect("Failed to parse JSON");
-                init_all_vars();
+            Commands::Uci { evaluator } => {
+                init_evaluator(&evaluator);
                 uci_loop();
             }
+            Commands::Eval { fens, evaluator } => {
+                init_evaluator(&evaluator);
+                evaluate_fen_file(&fens).expect("Failed to read FEN file");
+            }
+            Commands::EvalServer { listen, delay, evaluator } => {
+                init_evaluator(&evaluator);
+                if let Err(e) = external_eval::run_eval_server(listen.as_deref(), delay) {
+                    eprintln!("Evaluation server failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
             Commands::LinearRegression { input } => {
                 let inputs = vec![0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,0,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1];
                 let inputs: Vec<f64> = inputs.into_iter().map(|v| v as f64).collect();
 
                 // Load the model from JSON
-                let json_data = std::fs::read_to_string(&input).expect("Failed to read JSON file");
-                let model: _linear_regression::LinearModel = serde_json::from_str(&json_data).expect("Failed to parse JSON");
+                let model = LinearModel::load(&input).expect("Failed to load linear model");
 
                 // Example input data
                 let prediction = _linear_regression::predict(&model, &inputs);
 
                 println!("Prediction: {}", prediction);
             }
+            Commands::TrainLinear { input, output, loss, ridge, delta, sigmoid_scale, validation, iterations, target_scale } => {
+                let loss = match loss.to_lowercase().as_str() {
+                    "squared" => Loss::Squared,
+                    "huber" => Loss::Huber(delta),
+                    "logistic" => Loss::Logistic(sigmoid_scale),
+                    _ => {
+                        eprintln!("Unknown loss {} (expected squared, huber or logistic)", loss);
+                        std::process::exit(1);
+                    }
+                };
+                let options = TrainOptions { loss, ridge, validation, iterations, target_scale };
+                if let Err(e) = train_linear(&input, &output, &options) {
+                    eprintln!("Training failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Commands::FilterQuiet { input, output, margin, leaf, evaluator } => {
+                init_evaluator(&evaluator);
+                if let Err(e) = filter_quiet(&input, &output, margin, leaf) {
+                    eprintln!("Filtering failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Commands::Match { engine1, engine2, games, tc, depth, openings, random_plies, max_plies, elo0, elo1, alpha, beta, seed } => {
+                init_all_vars();
+                let options = MatchOptions { games, time_control: tc, depth, openings, random_plies, max_plies, elo0, elo1, alpha, beta, seed };
+                if let Err(e) = run_match(&engine1, &engine2, &options) {
+                    eprintln!("Match failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Commands::Datagen { output, games, depth, nodes, random_plies, max_plies, adjudicate, seed, evaluator } => {
+                init_evaluator(&evaluator);
+                let options = DatagenOptions { games, depth, nodes, random_plies, max_plies, adjudicate, seed };
+                if let Err(e) = generate_data(&output, &options) {
+                    eprintln!("Data generation failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Commands::Texel { input, output, init, k, epochs, learning_rate, validation } => {
+                let options = TexelOptions { init, k, epochs, learning_rate, validation };
+                if let Err(e) = texel_tune(&input, &output, &options) {
+                    eprintln!("Tuning failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Commands::Epd { input, depth, movetime, evaluator } => {
+                init_evaluator(&evaluator);
+                let movetime = if depth.is_none() && movetime.is_none() {Some(1000)} else {movetime};
+                if let Err(e) = run_epd_suite(&input, depth, movetime) {
+                    eprintln!("EPD suite failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Commands::Bench { depth, evaluator } => {
+                init_evaluator(&evaluator);
+                run_bench(depth);
+            }
+            Commands::EndgameGen { materials, output } => {
+                init_all_vars();
+                if let Err(e) = generate_tables(&materials, &output) {
+                    eprintln!("Endgame generation failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Commands::Book { input, output, max_plies, min_games, win_weight, draw_weight, loss_weight } => {
+                init_all_vars();
+                let options = BookOptions { max_plies, min_games, win_weight, draw_weight, loss_weight };
+                if let Err(e) = build_book(&input, &output, &options) {
+                    eprintln!("Book building failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Commands::Perft { epd, fen, depth, threads, hash } => {
+                init_all_vars();
+                match fen {
+                    Some(fen) if threads > 1 || hash > 0 => perft_parallel_test(&Board::new_from_fen(&fen), depth, threads, hash),
+                    Some(fen) => perft_test(&mut Board::new_from_fen(&fen), depth),
+                    None => {
+                        let passed = run_perft_suite(epd.as_deref(), depth, threads, hash).expect("Failed to read EPD file");
+                        if !passed {
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
         }
 
-        // -------------------- Subprocess -------------------------------------------
-        // let mut inputs = vec![0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,0,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1,1];
-        // // println!("{}", inputs.len());
-        // let (mut child, mut child_stdin, mut reader) = open_program();
-        // send_and_receive(&mut child, &mut child_stdin, &mut reader, inputs);
-        // close_connection(child_stdin, child);
-        // print!("sgdv\n")
-
-        // -------------------- Flask ------------------------------------------------
-        // let server_address = "127.0.0.1:5000";
-        // let mut stream = TcpStream::connect(server_address).unwrap();
-        // println!("{:?}", communicate(&mut stream, &server_address, &inputs));
-        // inputs[0] = 1;
-        // let mut stream = TcpStream::connect(server_address).unwrap();
-        // println!("{:?}", communicate(&mut stream, &server_address, &inputs));
-
         // ------------------- Json model -------------------------------------------
         // let mut board = Board::new_from_fen(START_POSITION);
         // board.make_move(parse_move(&board, "d2d4"), false);
@@ -229,27 +691,5 @@ fn main() {
         // let move_ = parse_move(&board, "e1g1");
         // board.make_move(move_, false);
         // board.print();
-
-        // ---------------- Conevrt to linear model ------------------------------------
-        // let mut all_scores: Vec<f64> = Vec::with_capacity(780);
-        // all_scores.extend(PAWN_SCORE.iter().map(|&x| x as f64 / 100.0));
-        // all_scores.extend(KNIGHT_SCORE.iter().map(|&x| x as f64 / 100.0));
-        // all_scores.extend(BISHOP_SCORE.iter().map(|&x| x as f64 / 100.0));
-        // all_scores.extend(ROOK_SCORE.iter().map(|&x| x as f64 / 100.0));
-        // all_scores.extend(KING_SCORE.iter().map(|&x| x as f64 / 100.0));
-        // all_scores.extend(MATERIAL_SCORE.iter().map(|&x| x as f64 / 100.0));
-
-        // // Create the JSON object
-        // let coefficients = LinearModel {
-        //     coefficients: all_scores,
-        //     intercept: 0.0,
-        // };
-
-        // // Serialize to JSON and write to file
-        // let json_data = serde_json::to_string_pretty(&coefficients).unwrap();
-        // let mut file = File::create("coefficients.json").expect("Unable to create file");
-        // file.write_all(json_data.as_bytes()).expect("Unable to write data");
-
-        // println!("JSON file 'coefficients.json' created successfully.");
     }
 }
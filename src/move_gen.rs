@@ -1,7 +1,7 @@
 use crate::moves::Moves;
-use crate::{Side, Square, get_bit, pop_bit, SQUARE_TO_COORDINATES, encode_move};
-use crate::bitboard::{Board, Pieces, get_ls1b_index, CastlingSide};
-use crate::attacks::{PAWN_ATTACKS, KNIGHT_ATTACKS, KING_ATTACKS, get_bishop_attacks, get_queen_attacks, get_rook_attacks};
+use crate::{Side, Square, get_bit, pop_bit, SQUARE_TO_COORDINATES, encode_move, get_move_source, get_move_target, get_move_piece, get_move_promoted, get_move_enpassant, get_move_castling};
+use crate::bitboard::{Board, Pieces, get_ls1b_index, count_bits, CastlingSide};
+use crate::attacks::{PAWN_ATTACKS, KNIGHT_ATTACKS, KING_ATTACKS, BETWEEN_MASKS, LINE_MASKS, get_bishop_attacks, get_queen_attacks, get_rook_attacks};
 
 #[inline(always)]
 pub unsafe fn is_square_attacked(board:&Board, square:usize, is_white_turn:bool) -> bool {
@@ -23,6 +23,20 @@ pub unsafe fn is_square_attacked(board:&Board, square:usize, is_white_turn:bool)
     false
 }
 
+// Bitboard of every piece (both sides) attacking a square for the given occupancy
+#[inline(always)]
+pub unsafe fn attackers_to(board:&Board, square:usize, occupancy:u64) -> u64 {
+    let bishops_queens = board.bitboards[2] | board.bitboards[4] | board.bitboards[8] | board.bitboards[10];
+    let rooks_queens = board.bitboards[3] | board.bitboards[4] | board.bitboards[9] | board.bitboards[10];
+
+    (PAWN_ATTACKS[1][square] & board.bitboards[0])
+        | (PAWN_ATTACKS[0][square] & board.bitboards[6])
+        | (KNIGHT_ATTACKS[square] & (board.bitboards[1] | board.bitboards[7]))
+        | (KING_ATTACKS[square] & (board.bitboards[5] | board.bitboards[11]))
+        | (get_bishop_attacks(square, occupancy) & bishops_queens)
+        | (get_rook_attacks(square, occupancy) & rooks_queens)
+}
+
 pub fn print_attacked_squares(board:&Board, is_white_turn:bool) {
     println!("\n");
     for rank in 0..8usize {
@@ -338,3 +352,344 @@ pub unsafe fn generate_moves(board:&Board) -> Moves {
     }
     moves
 }
+
+
+/******************************************\
+ ==========================================
+            Legal Move Generation
+ ==========================================
+\******************************************/
+
+#[inline(always)]
+pub unsafe fn in_check(board:&Board) -> bool {
+    let white = board.side == Side::White;
+    let king_square = get_ls1b_index(board.bitboards[if white {Pieces::K as usize} else {Pieces::k as usize}]);
+    is_square_attacked(board, king_square, !white)
+}
+
+// Every square attacked by one side, using the given occupancy for sliders
+pub unsafe fn attacked_squares(board:&Board, white:bool, occupancy:u64) -> u64 {
+    let offset = if white {0} else {6};
+    let mut attacked:u64 = 0;
+    let mut bb:u64;
+
+    for piece in offset..(offset+6) {
+        bb = board.bitboards[piece];
+        while bb != 0 {
+            let square = get_ls1b_index(bb);
+
+            attacked |= match piece - offset {
+                0 => PAWN_ATTACKS[if white {0} else {1}][square],
+                1 => KNIGHT_ATTACKS[square],
+                2 => get_bishop_attacks(square, occupancy),
+                3 => get_rook_attacks(square, occupancy),
+                4 => get_queen_attacks(square, occupancy),
+                _ => KING_ATTACKS[square],
+            };
+
+            pop_bit!(bb, square);
+        }
+    }
+
+    attacked
+}
+
+#[inline(always)]
+fn add_pawn_moves(moves:&mut Moves, source_square:usize, target_square:usize, piece:usize, capture:usize, promotion:bool) {
+    if promotion {
+        // Promoted pieces share the pawn's color offset
+        let offset = piece;
+        moves.add_move(encode_move!(source_square, target_square, piece, Pieces::Q as usize + offset, capture, 0, 0, 0));
+        moves.add_move(encode_move!(source_square, target_square, piece, Pieces::R as usize + offset, capture, 0, 0, 0));
+        moves.add_move(encode_move!(source_square, target_square, piece, Pieces::B as usize + offset, capture, 0, 0, 0));
+        moves.add_move(encode_move!(source_square, target_square, piece, Pieces::N as usize + offset, capture, 0, 0, 0));
+    } else {
+        moves.add_move(encode_move!(source_square, target_square, piece, 12, capture, 0, 0, 0));
+    }
+}
+
+/*
+    Generates only legal moves. Checkers, pinned pieces and the enemy attack map
+    (computed with our king removed, so it can't step back along a slider's ray)
+    are calculated up front:
+        - Double check: only king moves
+        - Single check: other pieces must capture the checker or block its ray
+        - Pinned pieces can only move along the line through the king
+        - En passant is validated by removing both pawns and looking for sliders
+          hitting the king (horizontal discovered check)
+*/
+pub unsafe fn generate_legal_moves(board:&Board) -> Moves {
+    let mut moves = Moves::new();
+
+    let white = board.side == Side::White;
+    let offset = if white {0} else {6};
+    let their_offset = if white {6} else {0};
+    let our_occupancy = board.occupancies[if white {0} else {1}];
+    let their_occupancy = board.occupancies[if white {1} else {0}];
+    let occupancy = board.occupancies[2];
+
+    let king = Pieces::K as usize + offset;
+    let king_square = get_ls1b_index(board.bitboards[king]);
+
+    let their_bishops_queens = board.bitboards[Pieces::B as usize + their_offset] | board.bitboards[Pieces::Q as usize + their_offset];
+    let their_rooks_queens = board.bitboards[Pieces::R as usize + their_offset] | board.bitboards[Pieces::Q as usize + their_offset];
+
+    // Enemy pieces giving check
+    let checkers = attackers_to(board, king_square, occupancy) & their_occupancy;
+    let n_checkers = count_bits(checkers);
+
+    // Squares the king can't move to
+    let danger = attacked_squares(board, !white, occupancy & !board.bitboards[king]);
+
+    // King moves
+    let mut attacks = KING_ATTACKS[king_square] & !our_occupancy & !danger;
+    while attacks != 0 {
+        let target_square = get_ls1b_index(attacks);
+        let capture = if get_bit!(their_occupancy, target_square) != 0 {1} else {0};
+        moves.add_move(encode_move!(king_square, target_square, king, 12, capture, 0, 0, 0));
+        pop_bit!(attacks, target_square);
+    }
+
+    // Only the king can escape a double check
+    if n_checkers > 1 {
+        return moves
+    }
+
+    // Squares other pieces may move to (capture the checker or block)
+    let check_mask = if n_checkers == 1 {
+        checkers | BETWEEN_MASKS[king_square][get_ls1b_index(checkers)]
+    } else {
+        !0u64
+    };
+
+    // Pinned pieces: our single blocker between the king and an enemy slider
+    let mut pinned:u64 = 0;
+    let mut snipers = (get_rook_attacks(king_square, their_occupancy) & their_rooks_queens)
+                    | (get_bishop_attacks(king_square, their_occupancy) & their_bishops_queens);
+    while snipers != 0 {
+        let sniper_square = get_ls1b_index(snipers);
+        let blockers = BETWEEN_MASKS[king_square][sniper_square] & occupancy;
+
+        if count_bits(blockers) == 1 && blockers & our_occupancy != 0 {
+            pinned |= blockers;
+        }
+
+        pop_bit!(snipers, sniper_square);
+    }
+
+    let pin_mask = |square:usize| -> u64 {
+        if get_bit!(pinned, square) != 0 {LINE_MASKS[king_square][square]} else {!0u64}
+    };
+
+    // Pawns
+    let pawn = Pieces::P as usize + offset;
+    let mut bb = board.bitboards[pawn];
+    while bb != 0 {
+        let source_square = get_ls1b_index(bb);
+        let allowed = check_mask & pin_mask(source_square);
+
+        let (target_square, promotion, double_rank) = if white {
+            (source_square - 8, source_square < Square::a6 as usize && source_square > Square::h8 as usize,
+             source_square > Square::h3 as usize && source_square < Square::a1 as usize)
+        } else {
+            (source_square + 8, source_square > Square::h3 as usize && source_square < Square::a1 as usize,
+             source_square < Square::a6 as usize && source_square > Square::h8 as usize)
+        };
+
+        // Pushes
+        if get_bit!(occupancy, target_square) == 0 {
+            if get_bit!(allowed, target_square) != 0 {
+                add_pawn_moves(&mut moves, source_square, target_square, pawn, 0, promotion);
+            }
+
+            if double_rank {
+                let double_target = if white {target_square - 8} else {target_square + 8};
+                if get_bit!(occupancy, double_target) == 0 && get_bit!(allowed, double_target) != 0 {
+                    moves.add_move(encode_move!(source_square, double_target, pawn, 12, 0, 1, 0, 0));
+                }
+            }
+        }
+
+        // Captures
+        attacks = PAWN_ATTACKS[if white {0} else {1}][source_square] & their_occupancy & allowed;
+        while attacks != 0 {
+            let target_square = get_ls1b_index(attacks);
+            add_pawn_moves(&mut moves, source_square, target_square, pawn, 1, promotion);
+            pop_bit!(attacks, target_square);
+        }
+
+        // Enpassant
+        if let Some(enpassant_square) = board.en_passant {
+            if PAWN_ATTACKS[if white {0} else {1}][source_square] & (1u64 << enpassant_square) != 0 {
+                let captured_square = if white {enpassant_square + 8} else {enpassant_square - 8};
+                let resolves_check = check_mask & ((1u64 << enpassant_square) | (1u64 << captured_square)) != 0;
+
+                // Board after the capture, as seen by the sliders
+                let occupancy_after = (occupancy & !(1u64 << source_square) & !(1u64 << captured_square)) | (1u64 << enpassant_square);
+                let exposes_king = (get_rook_attacks(king_square, occupancy_after) & their_rooks_queens) != 0
+                                || (get_bishop_attacks(king_square, occupancy_after) & their_bishops_queens) != 0;
+
+                if resolves_check && !exposes_king {
+                    moves.add_move(encode_move!(source_square, enpassant_square, pawn, 12, 1, 0, 1, 0));
+                }
+            }
+        }
+
+        pop_bit!(bb, source_square);
+    }
+
+    // Knights, bishops, rooks and queens
+    for piece in (Pieces::N as usize + offset)..(Pieces::K as usize + offset) {
+        bb = board.bitboards[piece];
+        while bb != 0 {
+            let source_square = get_ls1b_index(bb);
+
+            attacks = match piece - offset {
+                1 => KNIGHT_ATTACKS[source_square],
+                2 => get_bishop_attacks(source_square, occupancy),
+                3 => get_rook_attacks(source_square, occupancy),
+                _ => get_queen_attacks(source_square, occupancy),
+            } & !our_occupancy & check_mask & pin_mask(source_square);
+
+            while attacks != 0 {
+                let target_square = get_ls1b_index(attacks);
+                let capture = if get_bit!(their_occupancy, target_square) != 0 {1} else {0};
+                moves.add_move(encode_move!(source_square, target_square, piece, 12, capture, 0, 0, 0));
+                pop_bit!(attacks, target_square);
+            }
+
+            pop_bit!(bb, source_square);
+        }
+    }
+
+    // Castling (never out of check, nor through or into an attacked square)
+    if n_checkers == 0 {
+        let (kingside, queenside, e, d, c, b, f, g) = if white {
+            (CastlingSide::WK, CastlingSide::WQ, Square::e1, Square::d1, Square::c1, Square::b1, Square::f1, Square::g1)
+        } else {
+            (CastlingSide::BK, CastlingSide::BQ, Square::e8, Square::d8, Square::c8, Square::b8, Square::f8, Square::g8)
+        };
+        let (e, f, g, d, c, b) = (e as usize, f as usize, g as usize, d as usize, c as usize, b as usize);
+
+        if board.castle & kingside as u8 != 0
+            && get_bit!(occupancy, f) == 0 && get_bit!(occupancy, g) == 0
+            && get_bit!(danger, f) == 0 && get_bit!(danger, g) == 0 {
+            moves.add_move(encode_move!(e, g, king, 12, 0, 0, 0, 1));
+        }
+
+        if board.castle & queenside as u8 != 0
+            && get_bit!(occupancy, d) == 0 && get_bit!(occupancy, c) == 0 && get_bit!(occupancy, b) == 0
+            && get_bit!(danger, d) == 0 && get_bit!(danger, c) == 0 {
+            moves.add_move(encode_move!(e, c, king, 12, 0, 0, 0, 1));
+        }
+    }
+
+    moves
+}
+
+
+/******************************************\
+ ==========================================
+              Check Detection
+ ==========================================
+\******************************************/
+
+// Our pieces standing alone between one of our sliders and the enemy king
+#[inline(always)]
+unsafe fn discovered_check_candidates(board:&Board, white:bool, king_square:usize) -> u64 {
+    let offset = if white {0} else {6};
+    let our_occupancy = board.occupancies[if white {0} else {1}];
+    let our_bishops_queens = board.bitboards[Pieces::B as usize + offset] | board.bitboards[Pieces::Q as usize + offset];
+    let our_rooks_queens = board.bitboards[Pieces::R as usize + offset] | board.bitboards[Pieces::Q as usize + offset];
+
+    let mut candidates:u64 = 0;
+    let mut snipers = (get_rook_attacks(king_square, 0) & our_rooks_queens)
+                    | (get_bishop_attacks(king_square, 0) & our_bishops_queens);
+    while snipers != 0 {
+        let sniper_square = get_ls1b_index(snipers);
+        let blockers = BETWEEN_MASKS[king_square][sniper_square] & board.occupancies[2];
+
+        if count_bits(blockers) == 1 && blockers & our_occupancy != 0 {
+            candidates |= blockers;
+        }
+
+        pop_bit!(snipers, sniper_square);
+    }
+
+    candidates
+}
+
+// Squares from which a piece (of the side to move) attacks the given square
+#[inline(always)]
+unsafe fn piece_attacks_from(piece:usize, square:usize, occupancy:u64) -> u64 {
+    match piece {
+        0 => PAWN_ATTACKS[0][square],
+        6 => PAWN_ATTACKS[1][square],
+        1 | 7 => KNIGHT_ATTACKS[square],
+        2 | 8 => get_bishop_attacks(square, occupancy),
+        3 | 9 => get_rook_attacks(square, occupancy),
+        4 | 10 => get_queen_attacks(square, occupancy),
+        _ => KING_ATTACKS[square],
+    }
+}
+
+/*
+    Whether a (legal) move checks the opponent, without making it:
+        - Direct check: the moved (or promoted) piece attacks the king from the target square
+        - Discovered check: the piece leaves the line between one of our sliders and the king
+        - Enpassant and castling are resolved with the resulting occupancy
+*/
+pub unsafe fn gives_check(board:&Board, move_:usize) -> bool {
+    let white = board.side == Side::White;
+    let offset = if white {0} else {6};
+    let their_king_square = get_ls1b_index(board.bitboards[if white {Pieces::k as usize} else {Pieces::K as usize}]);
+
+    let source_square = get_move_source!(move_);
+    let target_square = get_move_target!(move_);
+    let piece = get_move_piece!(move_);
+    let promoted = get_move_promoted!(move_);
+
+    let source_bb = 1u64 << source_square;
+    let target_bb = 1u64 << target_square;
+    let occupancy = (board.occupancies[2] & !source_bb) | target_bb;
+    let king_bb = 1u64 << their_king_square;
+
+    // Direct check
+    let moved_piece = if promoted < 12 {promoted} else {piece};
+    if moved_piece % 6 != Pieces::K as usize && piece_attacks_from(moved_piece, target_square, occupancy) & king_bb != 0 {
+        return true
+    }
+
+    // Discovered check
+    if discovered_check_candidates(board, white, their_king_square) & source_bb != 0
+        && LINE_MASKS[their_king_square][source_square] & target_bb == 0 {
+        return true
+    }
+
+    let our_bishops_queens = board.bitboards[Pieces::B as usize + offset] | board.bitboards[Pieces::Q as usize + offset];
+    let our_rooks_queens = board.bitboards[Pieces::R as usize + offset] | board.bitboards[Pieces::Q as usize + offset];
+
+    // Enpassant: the captured pawn may also uncover a slider
+    if get_move_enpassant!(move_) {
+        let captured_square = if white {target_square + 8} else {target_square - 8};
+        let occupancy_after = occupancy & !(1u64 << captured_square);
+
+        return (get_rook_attacks(their_king_square, occupancy_after) & our_rooks_queens) != 0
+            || (get_bishop_attacks(their_king_square, occupancy_after) & our_bishops_queens) != 0
+    }
+
+    // Castling: only the rook can give check
+    if get_move_castling!(move_) {
+        let (rook_source, rook_target) = match target_square {
+            x if x == Square::g1 as usize => (Square::h1 as usize, Square::f1 as usize),
+            x if x == Square::c1 as usize => (Square::a1 as usize, Square::d1 as usize),
+            x if x == Square::g8 as usize => (Square::h8 as usize, Square::f8 as usize),
+            _ => (Square::a8 as usize, Square::d8 as usize),
+        };
+        let occupancy_after = (occupancy & !(1u64 << rook_source)) | (1u64 << rook_target);
+
+        return get_rook_attacks(rook_target, occupancy_after) & king_bb != 0
+    }
+
+    false
+}
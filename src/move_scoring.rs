@@ -15,7 +15,7 @@
 
 */
 
-use crate::{Board, Side, get_bit, pop_bit, get_move_capture, get_move_piece, get_move_target, moves::{Moves, move_str}, search::{PLY, MAX_PLY, FOLLOW_PV, PV_TABLE, SCORE_PV}};
+use crate::{Board, Side, get_bit, pop_bit, get_move_capture, get_move_piece, get_move_target, moves::{Moves, move_str}, search::{PLY, MAX_PLY, FOLLOW_PV, PV_TABLE, SCORE_PV}, see::see};
 
 // MVV LVA [attacker][victim]
 static MVV_LVA:[[i32;12];12] = [
@@ -34,32 +34,123 @@ static MVV_LVA:[[i32;12];12] = [
    [100, 200, 300, 400, 500, 600,  100, 200, 300, 400, 500, 600]
 ];
 
+// Winning/equal captures are tried before killers, losing ones after every quiet move
+const GOOD_CAPTURE_SCORE:i32 = 10000;
+const BAD_CAPTURE_SCORE:i32 = -10000;
+
+// Quiet moves answering the previous move with its best reply
+const COUNTER_MOVE_SCORE:i32 = 7000;
+
+// History entries stay within +-HISTORY_MAX (gravity update)
+pub const HISTORY_MAX:i32 = 4096;
+
 pub static mut KILLER_MOVES:[[usize;MAX_PLY];2] = [[0;MAX_PLY];2];
 pub static mut HISTORY_MOVES:[[i32;64];12] = [[0;64];12];
+// Refutation of the previous move [piece][target]
+pub static mut COUNTER_MOVES:[[usize;64];12] = [[0;64];12];
+// Continuation histories [previous piece][previous target][piece][target], one and two plies back
+pub static mut COUNTER_HISTORY:[[[[i32;64];12];64];12] = [[[[0;64];12];64];12];
+pub static mut FOLLOWUP_HISTORY:[[[[i32;64];12];64];12] = [[[[0;64];12];64];12];
+// Move played to reach each ply, 0 at the root and after a null move
+pub static mut MOVE_STACK:[usize;MAX_PLY+1] = [0;MAX_PLY+1];
+
+pub unsafe fn clear_move_ordering() {
+    KILLER_MOVES = [[0;MAX_PLY];2];
+    HISTORY_MOVES = [[0;64];12];
+    COUNTER_MOVES = [[0;64];12];
+    COUNTER_HISTORY = [[[[0;64];12];64];12];
+    FOLLOWUP_HISTORY = [[[[0;64];12];64];12];
+    MOVE_STACK = [0;MAX_PLY+1];
+}
 
+// Moves the entry towards +-HISTORY_MAX, by less the closer it already is
+fn apply_gravity(entry:&mut i32, bonus:i32) {
+    let bonus = bonus.clamp(-HISTORY_MAX, HISTORY_MAX);
+    *entry += bonus - *entry * bonus.abs() / HISTORY_MAX;
+}
 
-pub fn sort_moves(moves:&mut Moves, board:&Board) {
-    // let mut move_scores:Vec<i32> = Vec::with_capacity(moves.count);
-    
+pub fn history_bonus(depth:i32) -> i32 {
+    (16 * depth * depth).min(HISTORY_MAX / 4)
+}
+
+// Butterfly, counter-move and follow-up history of a quiet move played at ply
+pub unsafe fn quiet_history(move_:usize, ply:usize) -> i32 {
+    let (piece, target) = (get_move_piece!(move_), get_move_target!(move_));
+    let mut score = HISTORY_MOVES[piece][target];
+
+    let previous = MOVE_STACK[ply];
+    if previous != 0 {
+        score += COUNTER_HISTORY[get_move_piece!(previous)][get_move_target!(previous)][piece][target];
+    }
+    if ply > 0 && MOVE_STACK[ply - 1] != 0 {
+        let followed = MOVE_STACK[ply - 1];
+        score += FOLLOWUP_HISTORY[get_move_piece!(followed)][get_move_target!(followed)][piece][target];
+    }
+
+    score
+}
+
+unsafe fn update_quiet_history(move_:usize, ply:usize, bonus:i32) {
+    let (piece, target) = (get_move_piece!(move_), get_move_target!(move_));
+    apply_gravity(&mut HISTORY_MOVES[piece][target], bonus);
+
+    let previous = MOVE_STACK[ply];
+    if previous != 0 {
+        apply_gravity(&mut COUNTER_HISTORY[get_move_piece!(previous)][get_move_target!(previous)][piece][target], bonus);
+    }
+    if ply > 0 && MOVE_STACK[ply - 1] != 0 {
+        let followed = MOVE_STACK[ply - 1];
+        apply_gravity(&mut FOLLOWUP_HISTORY[get_move_piece!(followed)][get_move_target!(followed)][piece][target], bonus);
+    }
+}
 
-    // for c in 0..moves.count {
-    //     move_scores.push(score_move(moves.moves[c], board));
-    // }
+// Quiet beta cutoff: reward the move, penalise the quiets searched before it (malus)
+pub unsafe fn update_quiet_cutoff(best:usize, tried:&[usize], depth:i32, ply:usize) {
+    let bonus = history_bonus(depth);
+    for &move_ in tried {
+        update_quiet_history(move_, ply, -bonus);
+    }
+    update_quiet_history(best, ply, bonus);
 
-    // move_scores.sort();
+    let previous = MOVE_STACK[ply];
+    if previous != 0 {
+        COUNTER_MOVES[get_move_piece!(previous)][get_move_target!(previous)] = best;
+    }
+
+    KILLER_MOVES[1][ply] = KILLER_MOVES[0][ply];
+    KILLER_MOVES[0][ply] = best;
+}
+
+
+pub fn sort_moves(moves:&mut Moves, board:&Board) {
+    sort_moves_scored(moves, board);
+}
+
+// Same order as sort_moves, also returning the score of every move
+pub fn sort_moves_scored(moves:&mut Moves, board:&Board) -> Vec<i32> {
     unsafe {
-        moves.moves.sort_by_key(|move_| score_move(*move_, board));
-        moves.moves.reverse();
+        let mut scored:Vec<(i32, usize)> = moves.moves.iter().map(|&move_| (score_move(move_, board), move_)).collect();
+        scored.sort_by_key(|&(score, _)| score);
+        scored.reverse();
         SCORE_PV = false;
+
+        moves.moves = scored.iter().map(|&(_, move_)| move_).collect();
+        scored.into_iter().map(|(score, _)| score).collect()
     }
 }
+
+// Losing captures (SEE < 0) are the only captures scored below zero
+pub fn is_losing_capture(score:i32) -> bool {
+    score < 0
+}
 /*
     1. PV move
-    2. Captures in MVV/LVA
+    2. Good captures (SEE >= 0) in MVV/LVA
     3. 1st killer move
     4. 2st killer move
-    5. History moves
-    6. Unsorted moves
+    5. Counter move (refutation of the previous move)
+    6. Quiet moves by history (butterfly + counter-move + follow-up), within +-6144
+    7. Bad captures (SEE < 0) in MVV/LVA
 */
 pub unsafe fn score_move(move_:usize, board:&Board) -> i32 {
     if SCORE_PV {
@@ -81,7 +172,11 @@ pub unsafe fn score_move(move_:usize, board:&Board) -> i32 {
             }
         }
 
-        return MVV_LVA[get_move_piece!(move_)][target_piece] + 10000
+        if see(board, move_) < 0 {
+            return MVV_LVA[get_move_piece!(move_)][target_piece] + BAD_CAPTURE_SCORE
+        }
+
+        return MVV_LVA[get_move_piece!(move_)][target_piece] + GOOD_CAPTURE_SCORE
     } else {
         //Score 1st killer move
         if KILLER_MOVES[0][PLY] == move_ {
@@ -93,9 +188,14 @@ pub unsafe fn score_move(move_:usize, board:&Board) -> i32 {
             return 8000
         }
 
+        //Score counter move
+        else if MOVE_STACK[PLY] != 0 && COUNTER_MOVES[get_move_piece!(MOVE_STACK[PLY])][get_move_target!(MOVE_STACK[PLY])] == move_ {
+            return COUNTER_MOVE_SCORE
+        }
+
         //Score histoy move
         else {
-            return HISTORY_MOVES[get_move_piece!(move_)][get_move_target!(move_)]
+            return quiet_history(move_, PLY) / 2
         }
     }
 }
@@ -13,7 +13,7 @@
     1000 0000 0000 0000 0000 0000    castling flag       0x800000
 */
 
-use crate::{SQUARE_TO_COORDINATES, bitboard::ASCII_PIECES};
+use crate::{SQUARE_TO_COORDINATES, bitboard::{Board, ASCII_PIECES}, move_gen::{generate_legal_moves, in_check}};
 
 #[macro_export]
 macro_rules! encode_move {
@@ -120,6 +120,71 @@ pub fn move_str(move_:usize) -> String {
     }
 }
 
+// Standard algebraic notation (Nbd7, exd5, e8=Q+, O-O), the move must be legal on the board
+pub unsafe fn move_san(board:&Board, move_:usize) -> String {
+    let source = get_move_source!(move_);
+    let target = get_move_target!(move_);
+    let piece = get_move_piece!(move_) % 6;
+    let mut san = String::new();
+
+    if get_move_castling!(move_) {
+        san.push_str(if target % 8 == 6 {"O-O"} else {"O-O-O"});
+    } else if piece == 0 {
+        if get_move_capture!(move_) {
+            san.push_str(&SQUARE_TO_COORDINATES[source][..1]);
+            san.push('x');
+        }
+        san.push_str(SQUARE_TO_COORDINATES[target]);
+        if get_move_promoted!(move_) < 12 {
+            san.push('=');
+            san.push(ASCII_PIECES[get_move_promoted!(move_) % 6]);
+        }
+    } else {
+        san.push(ASCII_PIECES[piece]);
+
+        // Same piece type reaching the same square from elsewhere
+        let moves = generate_legal_moves(board);
+        let rivals:Vec<usize> = moves.moves[..moves.count].iter()
+            .filter(|&&other| get_move_piece!(other) == get_move_piece!(move_) && get_move_target!(other) == target && get_move_source!(other) != source)
+            .map(|&other| get_move_source!(other))
+            .collect();
+        if !rivals.is_empty() {
+            let coordinates = SQUARE_TO_COORDINATES[source];
+            if rivals.iter().all(|&other| other % 8 != source % 8) {
+                san.push_str(&coordinates[..1]);
+            } else if rivals.iter().all(|&other| other / 8 != source / 8) {
+                san.push_str(&coordinates[1..]);
+            } else {
+                san.push_str(coordinates);
+            }
+        }
+
+        if get_move_capture!(move_) {
+            san.push('x');
+        }
+        san.push_str(SQUARE_TO_COORDINATES[target]);
+    }
+
+    let mut after = board.clone();
+    after.make_legal_move(move_);
+    if in_check(&after) {
+        san.push(if generate_legal_moves(&after).count == 0 {'#'} else {'+'});
+    }
+
+    san
+}
+
+// Legal move written in SAN (check marks and annotations optional) or as in UCI, 0 if none
+pub unsafe fn parse_san(board:&Board, text:&str) -> usize {
+    let strip = |san:&str| san.trim_end_matches(['+', '#', '!', '?']).replace("e.p.", "").replace('0', "O").trim().to_string();
+    let wanted = strip(text);
+    let moves = generate_legal_moves(board);
+
+    moves.moves[..moves.count].iter()
+        .find(|&&move_| strip(&move_san(board, move_)) == wanted || move_str(move_) == text.trim())
+        .copied()
+        .unwrap_or(0)
+}
 
 // struct Move {
 //     source:usize,
@@ -147,3 +212,73 @@ pub fn move_str(move_:usize) -> String {
 //     }
 // }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SAN of every legal move in the position
+    unsafe fn all_san(fen:&str) -> Vec<String> {
+        let board = Board::new_from_fen(fen);
+        let moves = generate_legal_moves(&board);
+        moves.moves[..moves.count].iter().map(|&move_| move_san(&board, move_)).collect()
+    }
+
+    fn has(sans:&[String], san:&str) -> bool {
+        sans.iter().any(|other| other == san)
+    }
+
+    #[test]
+    fn san_disambiguates_by_file_rank_or_square() {
+        crate::init_all_vars();
+        unsafe {
+            let knights = all_san("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1");
+            assert!(has(&knights, "Nbd2") && has(&knights, "Nfd2") && !has(&knights, "Nd2"));
+
+            let rooks = all_san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1");
+            assert!(has(&rooks, "R1a3") && has(&rooks, "R5a3") && !has(&rooks, "Ra3"));
+
+            // Only the square tells the a1 queen apart from both others
+            let queens = all_san("4k3/8/8/8/8/Q7/8/Q1Q4K w - - 0 1");
+            assert!(has(&queens, "Qa1b2") && has(&queens, "Qcb2") && has(&queens, "Q3b2"));
+        }
+    }
+
+    #[test]
+    fn san_writes_promotions_castling_and_checks() {
+        crate::init_all_vars();
+        unsafe {
+            let promotions = all_san("3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1");
+            assert!(has(&promotions, "e8=Q") && has(&promotions, "e8=N") && has(&promotions, "exd8=Q"));
+
+            let castling = all_san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+            assert!(has(&castling, "O-O") && has(&castling, "O-O-O"));
+
+            let checks = all_san("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+            assert!(has(&checks, "Qg8#") && has(&checks, "Qg2+") && has(&checks, "Qh2"));
+        }
+    }
+
+    #[test]
+    fn parse_san_finds_the_written_move() {
+        crate::init_all_vars();
+        unsafe {
+            for fen in ["4k3/8/8/8/8/Q7/8/Q1Q4K w - - 0 1", "3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1", "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"] {
+                let board = Board::new_from_fen(fen);
+                let moves = generate_legal_moves(&board);
+                for &move_ in &moves.moves[..moves.count] {
+                    assert_eq!(parse_san(&board, &move_san(&board, move_)), move_, "{} {}", fen, move_san(&board, move_));
+                    assert_eq!(parse_san(&board, &move_str(move_)), move_);
+                }
+            }
+
+            let board = Board::new_from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+            assert_eq!(move_san(&board, parse_san(&board, "0-0-0")), "O-O-O");
+            assert_eq!(move_san(&board, parse_san(&board, "e1g1")), "O-O");
+
+            // Ambiguous or illegal moves are not guessed
+            assert_eq!(parse_san(&Board::new_from_fen("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1"), "Nd2"), 0);
+            assert_eq!(parse_san(&Board::new_from_fen("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"), "Qh9"), 0);
+        }
+    }
+}
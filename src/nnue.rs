@@ -0,0 +1,262 @@
+/******************************************\
+ ==========================================
+        Efficiently Updatable Network
+ ==========================================
+\******************************************/
+
+/*
+    Network: (768 -> HIDDEN_SIZE) x 2 -> 1
+
+    The first layer sees the 768 piece-square features (12 pieces x 64 squares) from
+    both perspectives. White's perspective uses the pieces and squares as they are,
+    Black's perspective swaps the piece colours and flips the board vertically, so a
+    single set of weights serves both sides. Its output (the accumulator) only changes
+    by a few weight rows per move, so make_move adds and subtracts those rows instead
+    of recomputing the layer.
+
+    The output layer reads the clipped accumulators (side to move first, then the
+    opponent): clamp(x, 0, QA) and returns centipawns for the side to move.
+
+    Quantization: feature weights and biases are int16 in units of 1/QA, output weights
+    are int8 in units of 1/QB and the output bias is int32 in units of 1/(QA*QB).
+    The evaluation is output * OUTPUT_SCALE / (QA * QB).
+
+    Binary file format (little endian):
+        magic               4 bytes     "NNUE"
+        version             u32         1
+        hidden size         u32         must be HIDDEN_SIZE
+        feature weights     768 x HIDDEN_SIZE i16, one row per feature (piece * 64 + square)
+        feature biases      HIDDEN_SIZE i16
+        output weights      2 x HIDDEN_SIZE i8, side to move row then opponent row
+        output bias         i32
+*/
+
+use std::{error::Error, fs, ptr::addr_of};
+
+use crate::{bitboard::{Board, get_ls1b_index}, get_bit, pop_bit, Side};
+
+pub const HIDDEN_SIZE:usize = 256;
+const FEATURES:usize = 768;
+
+const QA:i32 = 255;
+const QB:i32 = 64;
+const OUTPUT_SCALE:i32 = 400;
+
+const NNUE_MAGIC:&[u8;4] = b"NNUE";
+const NNUE_VERSION:u32 = 1;
+
+// First layer output for White's and Black's perspective
+#[derive(Clone, Copy)]
+pub struct Accumulator {
+    pub values:[[i16;HIDDEN_SIZE];2],
+}
+
+impl Accumulator {
+    pub const fn new() -> Accumulator {
+        Accumulator { values: [[0;HIDDEN_SIZE];2] }
+    }
+}
+
+pub struct Network {
+    feature_weights:Vec<[i16;HIDDEN_SIZE]>,
+    feature_biases:[i16;HIDDEN_SIZE],
+    output_weights:[[i8;HIDDEN_SIZE];2],
+    output_bias:i32,
+}
+
+// Loaded network, accumulators are only maintained while there is one
+static mut NETWORK:Option<Box<Network>> = None;
+
+pub unsafe fn network_loaded() -> bool {
+    (*addr_of!(NETWORK)).is_some()
+}
+
+// Little endian reader over the network file
+struct Reader<'a> {
+    bytes:&'a [u8],
+    position:usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count:usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.position + count > self.bytes.len() {
+            return Err("network file is truncated".into())
+        }
+        let slice = &self.bytes[self.position..self.position+count];
+        self.position += count;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, Box<dyn Error>> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn i16_row(&mut self, row:&mut [i16;HIDDEN_SIZE]) -> Result<(), Box<dyn Error>> {
+        for (value, bytes) in row.iter_mut().zip(self.take(2*HIDDEN_SIZE)?.chunks_exact(2)) {
+            *value = i16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+
+    fn i8_row(&mut self, row:&mut [i8;HIDDEN_SIZE]) -> Result<(), Box<dyn Error>> {
+        for (value, &byte) in row.iter_mut().zip(self.take(HIDDEN_SIZE)?) {
+            *value = byte as i8;
+        }
+        Ok(())
+    }
+}
+
+pub fn read_network(path:&str) -> Result<Network, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let mut reader = Reader { bytes: &bytes, position: 0 };
+
+    if reader.take(4)? != NNUE_MAGIC {
+        return Err("not an NNUE network file".into())
+    }
+    let version = reader.u32()?;
+    if version != NNUE_VERSION {
+        return Err(format!("unsupported network version {}", version).into())
+    }
+    let hidden_size = reader.u32()? as usize;
+    if hidden_size != HIDDEN_SIZE {
+        return Err(format!("network has {} hidden neurons, expected {}", hidden_size, HIDDEN_SIZE).into())
+    }
+
+    let mut network = Network {
+        feature_weights: vec![[0;HIDDEN_SIZE]; FEATURES],
+        feature_biases: [0;HIDDEN_SIZE],
+        output_weights: [[0;HIDDEN_SIZE];2],
+        output_bias: 0,
+    };
+    for row in network.feature_weights.iter_mut() {
+        reader.i16_row(row)?;
+    }
+    reader.i16_row(&mut network.feature_biases)?;
+    for row in network.output_weights.iter_mut() {
+        reader.i8_row(row)?;
+    }
+    network.output_bias = reader.i32()?;
+
+    if reader.position != bytes.len() {
+        return Err("unexpected data after the network".into())
+    }
+
+    Ok(network)
+}
+
+pub unsafe fn load_network(path:&str) -> Result<(), Box<dyn Error>> {
+    NETWORK = Some(Box::new(read_network(path)?));
+    Ok(())
+}
+
+#[inline(always)]
+fn feature_index(perspective:usize, piece:usize, square:usize) -> usize {
+    if perspective == 0 {piece*64 + square} else {((piece+6) % 12)*64 + (square ^ 56)}
+}
+
+/*
+    Accumulator updates. The loops run over fixed size i16 arrays so the compiler
+    turns them into SIMD additions.
+*/
+#[inline(always)]
+fn add_row(values:&mut [i16;HIDDEN_SIZE], row:&[i16;HIDDEN_SIZE]) {
+    for (value, weight) in values.iter_mut().zip(row.iter()) {
+        *value += *weight;
+    }
+}
+
+#[inline(always)]
+fn sub_row(values:&mut [i16;HIDDEN_SIZE], row:&[i16;HIDDEN_SIZE]) {
+    for (value, weight) in values.iter_mut().zip(row.iter()) {
+        *value -= *weight;
+    }
+}
+
+#[inline(always)]
+fn sub_add_row(values:&mut [i16;HIDDEN_SIZE], removed:&[i16;HIDDEN_SIZE], added:&[i16;HIDDEN_SIZE]) {
+    for ((value, removed), added) in values.iter_mut().zip(removed.iter()).zip(added.iter()) {
+        *value += *added - *removed;
+    }
+}
+
+#[inline(always)]
+pub unsafe fn nnue_add_piece(accumulator:&mut Accumulator, piece:usize, square:usize) {
+    let Some(network) = &*addr_of!(NETWORK) else { return };
+    for perspective in 0..2 {
+        add_row(&mut accumulator.values[perspective], &network.feature_weights[feature_index(perspective, piece, square)]);
+    }
+}
+
+#[inline(always)]
+pub unsafe fn nnue_remove_piece(accumulator:&mut Accumulator, piece:usize, square:usize) {
+    let Some(network) = &*addr_of!(NETWORK) else { return };
+    for perspective in 0..2 {
+        sub_row(&mut accumulator.values[perspective], &network.feature_weights[feature_index(perspective, piece, square)]);
+    }
+}
+
+#[inline(always)]
+pub unsafe fn nnue_move_piece(accumulator:&mut Accumulator, piece:usize, source_square:usize, target_square:usize) {
+    let Some(network) = &*addr_of!(NETWORK) else { return };
+    for perspective in 0..2 {
+        sub_add_row(
+            &mut accumulator.values[perspective],
+            &network.feature_weights[feature_index(perspective, piece, source_square)],
+            &network.feature_weights[feature_index(perspective, piece, target_square)]
+        );
+    }
+}
+
+// Full recomputation of the accumulator from the pieces on the board
+pub unsafe fn refresh_accumulator(board:&mut Board) {
+    let Some(network) = &*addr_of!(NETWORK) else { return };
+    let mut bb:u64;
+
+    for perspective in 0..2 {
+        let values = &mut board.accumulator.values[perspective];
+        *values = network.feature_biases;
+
+        for piece in 0..12 {
+            bb = board.bitboards[piece];
+
+            while bb != 0 {
+                let square = get_ls1b_index(bb);
+
+                add_row(values, &network.feature_weights[feature_index(perspective, piece, square)]);
+
+                pop_bit!(bb, square);
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn clipped_dot(values:&[i16;HIDDEN_SIZE], weights:&[i8;HIDDEN_SIZE]) -> i32 {
+    values.iter().zip(weights.iter())
+        .map(|(&value, &weight)| (value as i32).clamp(0, QA) * weight as i32)
+        .sum()
+}
+
+// Centipawns from the side to move's point of view
+pub unsafe fn evaluate_nnue(board:&Board) -> i32 {
+    let Some(network) = &*addr_of!(NETWORK) else { return 0 };
+    let (us, them) = if board.side == Side::White {(0, 1)} else {(1, 0)};
+
+    let output = network.output_bias
+        + clipped_dot(&board.accumulator.values[us], &network.output_weights[0])
+        + clipped_dot(&board.accumulator.values[them], &network.output_weights[1]);
+
+    // In i64: a large output times OUTPUT_SCALE overflows i32
+    (output as i64 * OUTPUT_SCALE as i64 / (QA * QB) as i64) as i32
+}
+
+// Compares the incremental accumulator against a full refresh
+pub unsafe fn accumulator_in_sync(board:&Board) -> bool {
+    let mut refreshed = board.clone();
+    refresh_accumulator(&mut refreshed);
+    refreshed.accumulator.values == board.accumulator.values
+}
@@ -1,7 +1,13 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::time::Instant;
 
 use crate::bitboard::Board;
-use crate::move_gen::generate_moves;
+use crate::{START_POSITION, TRICKY_POSITION, KILLER_POSITION, CMK_POSITION};
+use crate::move_gen::{generate_legal_moves, generate_moves};
 use crate::moves;
 use crate::uci::duration_as_ms;
 
@@ -13,13 +19,11 @@ pub unsafe fn perft_driver(board:&mut Board, depth:isize) {
         return
     }
 
-    let moves = generate_moves(&board);
+    let moves = generate_legal_moves(board);
 
     for c in 0..moves.count {
         let new_board = board.clone();
-        if !board.make_move(moves.moves[c], false) {
-            continue;
-        }
+        board.make_legal_move(moves.moves[c]);
         perft_driver(board, depth-1);
         *board = new_board;
     }
@@ -29,15 +33,13 @@ pub unsafe fn perft_test(board:&mut Board, depth:isize) {
     println!("\tPerformance test");
 
     let start = Instant::now();
-    let moves = generate_moves(&board);
+    let moves = generate_legal_moves(board);
     let mut commulative_nodes:u64;
     let mut old_nodes:u64;
 
     for c in 0..moves.count {
         let new_board = board.clone();
-        if !board.make_move(moves.moves[c], false) {
-            continue;
-        }
+        board.make_legal_move(moves.moves[c]);
         commulative_nodes = NODES;
         perft_driver(board, depth-1);
         old_nodes = NODES - commulative_nodes;
@@ -50,4 +52,368 @@ pub unsafe fn perft_test(board:&mut Board, depth:isize) {
     println!("\n\n\tDepth: {}", depth);
     println!("\tNodes: {}", NODES);
     println!("\tTime: {}", duration_as_ms(since_the_epoch));
-}
\ No newline at end of file
+}
+
+/******************************************\
+ ==========================================
+               Perft Suite
+ ==========================================
+\******************************************/
+
+/*
+    Built-in positions in perftsuite EPD format ("fen ;D1 nodes ;D2 nodes ...").
+    Besides the engine's own test positions it includes the chessprogramming wiki
+    positions, perftsuite castling/promotion endings and the Talkchess edge cases
+    (illegal enpassant, discovered checks, castling through check).
+*/
+pub const PERFT_SUITE:[&str;33] = [
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1 ;D1 14 ;D2 191 ;D3 2812 ;D4 43238 ;D5 674624 ;D6 11030083",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1 ;D1 6 ;D2 264 ;D3 9467 ;D4 422333 ;D5 15833292",
+    "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1 ;D1 6 ;D2 264 ;D3 9467 ;D4 422333",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8 ;D1 44 ;D2 1486 ;D3 62379 ;D4 2103487",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10 ;D1 46 ;D2 2079 ;D3 89890 ;D4 3894594",
+    "4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1 15 ;D2 66 ;D3 1197 ;D4 7059 ;D5 133987 ;D6 764643",
+    "4k3/8/8/8/8/8/8/R3K3 w Q - 0 1 ;D1 16 ;D2 71 ;D3 1287 ;D4 7626 ;D5 145232 ;D6 846648",
+    "4k2r/8/8/8/8/8/8/4K3 w k - 0 1 ;D1 5 ;D2 75 ;D3 459 ;D4 8290 ;D5 47635 ;D6 899442",
+    "r3k3/8/8/8/8/8/8/4K3 w q - 0 1 ;D1 5 ;D2 80 ;D3 493 ;D4 8897 ;D5 52710 ;D6 1001523",
+    "4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1 ;D1 26 ;D2 112 ;D3 3189 ;D4 17945 ;D5 532933 ;D6 2788982",
+    "r3k2r/8/8/8/8/8/8/4K3 w kq - 0 1 ;D1 5 ;D2 130 ;D3 782 ;D4 22180 ;D5 118882 ;D6 3517770",
+    "8/8/8/8/8/8/6k1/4K2R w K - 0 1 ;D1 12 ;D2 38 ;D3 564 ;D4 2219 ;D5 37735 ;D6 185867",
+    "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1 ;D1 26 ;D2 568 ;D3 13744 ;D4 314346 ;D5 7594526",
+    "8/1n4N1/2k5/8/8/5K2/1N4n1/8 w - - 0 1 ;D1 14 ;D2 195 ;D3 2760 ;D4 38675 ;D5 570726 ;D6 8107539",
+    "K7/8/2n5/1n6/8/8/8/k6N w - - 0 1 ;D1 3 ;D2 51 ;D3 345 ;D4 5301 ;D5 38348 ;D6 588695",
+    "7k/RR6/8/8/8/8/rr6/7K w - - 0 1 ;D1 19 ;D2 275 ;D3 5300 ;D4 104342 ;D5 2161211",
+    "8/Pk6/8/8/8/8/6Kp/8 w - - 0 1 ;D1 11 ;D2 97 ;D3 887 ;D4 8048 ;D5 90606 ;D6 1030499",
+    "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N w - - 0 1 ;D1 24 ;D2 496 ;D3 9483 ;D4 182838 ;D5 3605103",
+    "8/PPPk4/8/8/8/8/4Kppp/8 w - - 0 1 ;D1 18 ;D2 270 ;D3 4699 ;D4 79355 ;D5 1533145",
+    "3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1 ;D6 1134888",
+    "8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1 ;D6 1015133",
+    "8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1 ;D6 1440467",
+    "5k2/8/8/8/8/8/8/4K2R w K - 0 1 ;D6 661072",
+    "3k4/8/8/8/8/8/8/R3K3 w Q - 0 1 ;D6 803711",
+    "r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1 ;D4 1274206",
+    "r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1 ;D4 1720476",
+    "2K2r2/4P3/8/8/8/8/8/3k4 w - - 0 1 ;D6 3821001",
+    "8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1 ;D5 1004658",
+    "4k3/1P6/8/8/8/8/K7/8 w - - 0 1 ;D6 217342",
+    "8/P1k5/K7/8/8/8/8/8 w - - 0 1 ;D6 92683",
+    "K1k5/8/P7/8/8/8/8/8 w - - 0 1 ;D6 2217",
+    "8/k1P5/8/1K6/8/8/8/8 w - - 0 1 ;D7 567584",
+    "8/8/2k5/5q2/5n2/8/5K2/8 b - - 0 1 ;D4 23527",
+];
+
+// Engine test positions (main.rs) with their node counts
+const ENGINE_POSITIONS:[(&str, &[(isize, u64)]);4] = [
+    (START_POSITION, &[(1, 20), (2, 400), (3, 8902), (4, 197281), (5, 4865609)]),
+    (TRICKY_POSITION, &[(1, 48), (2, 2039), (3, 97862), (4, 4085603)]),
+    (KILLER_POSITION, &[(1, 41), (2, 1059), (3, 38395), (4, 1001961)]),
+    (CMK_POSITION, &[(1, 43), (2, 1289), (3, 54240), (4, 1679340)]),
+];
+
+// Parse an EPD perft line into its FEN and (depth, expected nodes) pairs
+pub fn parse_perft_epd(line:&str) -> Option<(String, Vec<(isize, u64)>)> {
+    let mut fields = line.split(';');
+    let fen = fields.next()?.trim().to_string();
+    if fen.is_empty() || fen.starts_with('#') {
+        return None
+    }
+
+    let mut expected = Vec::new();
+    for field in fields {
+        let mut tokens = field.split_whitespace();
+        if let (Some(depth), Some(nodes)) = (tokens.next(), tokens.next()) {
+            if let (Ok(depth), Ok(nodes)) = (depth.trim_start_matches('D').parse::<isize>(), nodes.parse::<u64>()) {
+                expected.push((depth, nodes));
+            }
+        }
+    }
+
+    Some((fen, expected))
+}
+
+// Node count with the perft driver
+pub unsafe fn perft_nodes(board:&mut Board, depth:isize) -> u64 {
+    NODES = 0;
+    perft_driver(board, depth);
+    NODES
+}
+
+// Reference perft: pseudo-legal moves filtered by make_move
+pub unsafe fn perft_pseudo_legal(board:&mut Board, depth:isize) -> u64 {
+    if depth == 0 {
+        return 1
+    }
+
+    let moves = generate_moves(board);
+    let mut nodes = 0;
+
+    for c in 0..moves.count {
+        let new_board = board.clone();
+        if !board.make_move(moves.moves[c], false) {
+            continue;
+        }
+        nodes += perft_pseudo_legal(board, depth-1);
+        *board = new_board;
+    }
+
+    nodes
+}
+
+// Nodes below every root move, keyed by move string
+pub unsafe fn perft_divide(board:&mut Board, depth:isize, pseudo_legal:bool) -> BTreeMap<String, u64> {
+    let mut divide = BTreeMap::new();
+    let moves = if pseudo_legal {generate_moves(board)} else {generate_legal_moves(board)};
+
+    for c in 0..moves.count {
+        let new_board = board.clone();
+        if !board.make_move(moves.moves[c], false) {
+            continue;
+        }
+        let nodes = if pseudo_legal {perft_pseudo_legal(board, depth-1)} else {perft_nodes(board, depth-1)};
+        divide.insert(moves::move_str(moves.moves[c]), nodes);
+        *board = new_board;
+    }
+
+    divide
+}
+
+// Compare the legal generator against the pseudo-legal reference move by move
+pub unsafe fn print_divide_diff(board:&mut Board, depth:isize) {
+    let legal = perft_divide(board, depth, false);
+    let reference = perft_divide(board, depth, true);
+
+    println!("\t  Move      Legal      Reference");
+    for move_ in legal.keys().chain(reference.keys().filter(|m| !legal.contains_key(*m))) {
+        let legal_nodes = legal.get(move_);
+        let reference_nodes = reference.get(move_);
+        if legal_nodes != reference_nodes {
+            println!("\t  {:<8}  {:<9}  {}", move_,
+                legal_nodes.map_or("-".to_string(), |n| n.to_string()),
+                reference_nodes.map_or("-".to_string(), |n| n.to_string()));
+        }
+    }
+    println!("\t  Total     {:<9}  {}", legal.values().sum::<u64>(), reference.values().sum::<u64>());
+}
+
+/*
+    Runs every position (built-in suite or an EPD file) up to max_depth and
+    prints a divide diff for each mismatch. Returns whether everything matched.
+    With more than one thread or a hash size, the parallel hashed perft is used.
+*/
+pub unsafe fn run_perft_suite(epd_file:Option<&str>, max_depth:isize, threads:usize, hash_mb:usize) -> Result<bool, Box<dyn Error>> {
+    let lines:Vec<String> = match epd_file {
+        Some(path) => std::fs::read_to_string(path)?.lines().map(|l| l.to_string()).collect(),
+        None => ENGINE_POSITIONS.iter()
+            .map(|(fen, counts)| counts.iter().fold(fen.to_string(), |line, (depth, nodes)| format!("{} ;D{} {}", line, depth, nodes)))
+            .chain(PERFT_SUITE.iter().map(|l| l.to_string()))
+            .collect(),
+    };
+
+    let parallel = threads > 1 || hash_mb > 0;
+    let mut caches = if parallel {new_perft_caches(threads, hash_mb)} else {Vec::new()};
+
+    let start = Instant::now();
+    let mut total_nodes:u64 = 0;
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+
+    for line in lines.iter() {
+        let (fen, expected) = match parse_perft_epd(line) {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        println!("\n\tPosition: {}", fen);
+
+        let too_deep = expected.iter().filter(|&&(depth, _)| depth > max_depth).count();
+        skipped += too_deep;
+        if too_deep == expected.len() {
+            println!("\t  skipped (depth > {})", max_depth);
+            continue;
+        }
+
+        for (depth, expected_nodes) in expected {
+            if depth > max_depth {
+                continue;
+            }
+
+            let mut board = Board::new_from_fen(&fen);
+            let nodes = if parallel {
+                perft_parallel(&board, depth, &mut caches)
+            } else {
+                perft_nodes(&mut board, depth)
+            };
+            total_nodes += nodes;
+
+            if nodes == expected_nodes {
+                passed += 1;
+                println!("\t  Depth {}: {} ok", depth, nodes);
+            } else {
+                failed += 1;
+                println!("\t  Depth {}: {} expected {} MISMATCH", depth, nodes, expected_nodes);
+                print_divide_diff(&mut board, depth);
+            }
+        }
+    }
+
+    println!("\n\tPassed: {}   Failed: {}   Skipped (depth > {}): {}", passed, failed, max_depth, skipped);
+    println!("\tNodes: {}", total_nodes);
+    println!("\tTime: {}", duration_as_ms(start.elapsed()));
+
+    Ok(failed == 0)
+}
+
+
+/******************************************\
+ ==========================================
+         Parallel & Hashed Perft
+ ==========================================
+\******************************************/
+
+#[derive(Copy, Clone)]
+struct PerftEntry {
+    hash_key:u64,
+    depth:isize,
+    nodes:u64,
+}
+
+// Subtree node counts keyed by position hash and remaining depth (one per thread)
+pub struct PerftCache {
+    entries:Vec<PerftEntry>,
+    mask:usize,
+}
+
+impl PerftCache {
+    pub fn new(size_mb:usize) -> PerftCache {
+        let max_entries = size_mb * 1024 * 1024 / std::mem::size_of::<PerftEntry>();
+        // Round down to a power of two so the index is a simple mask
+        let size = if max_entries == 0 {0} else {1usize << (usize::BITS - 1 - max_entries.leading_zeros())};
+
+        PerftCache {
+            entries: vec![PerftEntry { hash_key:0, depth:0, nodes:0 }; size],
+            mask: size.wrapping_sub(1),
+        }
+    }
+
+    #[inline(always)]
+    fn index(&self, hash_key:u64, depth:isize) -> usize {
+        (hash_key as usize).wrapping_add(depth as usize) & self.mask
+    }
+
+    fn probe(&self, hash_key:u64, depth:isize) -> Option<u64> {
+        if self.entries.is_empty() {
+            return None
+        }
+
+        let entry = &self.entries[self.index(hash_key, depth)];
+        if entry.hash_key == hash_key && entry.depth == depth {Some(entry.nodes)} else {None}
+    }
+
+    fn store(&mut self, hash_key:u64, depth:isize, nodes:u64) {
+        if self.entries.is_empty() {
+            return
+        }
+
+        let index = self.index(hash_key, depth);
+        self.entries[index] = PerftEntry { hash_key, depth, nodes };
+    }
+}
+
+// Perft with bulk counting at depth 1 and a subtree cache
+pub unsafe fn perft_hashed(board:&mut Board, depth:isize, cache:&mut PerftCache) -> u64 {
+    if depth == 0 {
+        return 1
+    }
+
+    let moves = generate_legal_moves(board);
+
+    // Bulk counting: every legal move is a leaf
+    if depth == 1 {
+        return moves.count as u64
+    }
+
+    if let Some(nodes) = cache.probe(board.hash_key, depth) {
+        return nodes
+    }
+
+    let mut nodes = 0;
+    for c in 0..moves.count {
+        let new_board = board.clone();
+        board.make_legal_move(moves.moves[c]);
+        nodes += perft_hashed(board, depth-1, cache);
+        *board = new_board;
+    }
+
+    cache.store(board.hash_key, depth, nodes);
+
+    nodes
+}
+
+// One cache per worker thread, splitting hash_mb between them
+pub fn new_perft_caches(threads:usize, hash_mb:usize) -> Vec<PerftCache> {
+    let threads = threads.max(1);
+    (0..threads).map(|_| PerftCache::new(hash_mb / threads)).collect()
+}
+
+/*
+    Root moves are handed out to the worker threads (one per cache) one at a time.
+    Caches can be reused between calls, entries are keyed by position.
+    Returns the node count below every root move, in move generation order.
+*/
+pub unsafe fn perft_parallel_divide(board:&Board, depth:isize, caches:&mut [PerftCache]) -> Vec<(usize, u64)> {
+    let moves = generate_legal_moves(board);
+    let next_move = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(moves.count));
+
+    thread::scope(|scope| {
+        for cache in caches.iter_mut() {
+            let (moves, next_move, results) = (&moves, &next_move, &results);
+            scope.spawn(move || {
+                let mut board = board.clone();
+
+                loop {
+                    let c = next_move.fetch_add(1, Ordering::Relaxed);
+                    if c >= moves.count {
+                        break;
+                    }
+
+                    let root_board = board.clone();
+                    board.make_legal_move(moves.moves[c]);
+                    let nodes = perft_hashed(&mut board, depth-1, cache);
+                    board = root_board;
+
+                    results.lock().unwrap().push((c, moves.moves[c], nodes));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(c, _, _)| *c);
+    results.into_iter().map(|(_, move_, nodes)| (move_, nodes)).collect()
+}
+
+pub unsafe fn perft_parallel(board:&Board, depth:isize, caches:&mut [PerftCache]) -> u64 {
+    if depth < 1 {
+        return 1
+    }
+    perft_parallel_divide(board, depth, caches).iter().map(|(_, nodes)| nodes).sum()
+}
+
+pub unsafe fn perft_parallel_test(board:&Board, depth:isize, threads:usize, hash_mb:usize) {
+    println!("\tPerformance test ({} threads, {} MB hash)", threads, hash_mb);
+
+    let start = Instant::now();
+    let mut nodes:u64 = 0;
+
+    for (move_, move_nodes) in perft_parallel_divide(board, depth, &mut new_perft_caches(threads, hash_mb)) {
+        nodes += move_nodes;
+        println!("\tMove: {}   Nodes: {}", moves::move_str(move_), move_nodes);
+    }
+
+    let since_the_epoch = start.elapsed();
+    println!("\n\n\tDepth: {}", depth);
+    println!("\tNodes: {}", nodes);
+    println!("\tTime: {}", duration_as_ms(since_the_epoch));
+}
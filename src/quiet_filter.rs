@@ -0,0 +1,154 @@
+/******************************************\
+ ==========================================
+             Quiet Position Filter
+ ==========================================
+\******************************************/
+
+/*
+    Filtering stage run before process-csv. A position is quiet when its static
+    evaluation is within a margin of the quiescence search result, otherwise a capture
+    sequence is pending and the static evaluation says little about the label.
+
+    Tactical positions are dropped, or with --leaf replaced by the quiet position at the
+    end of the quiescence principal variation (the label is White relative, so it is
+    kept as is). Positions in check, illegal positions and unreadable rows are always
+    dropped. The other columns of each row (such as the datagen result) are copied
+    unchanged.
+*/
+
+use std::error::Error;
+use std::time::Instant;
+
+use csv::{ReaderBuilder, WriterBuilder};
+
+use crate::{bitboard::Board, eval::evaluate, get_move_capture, move_gen::{generate_legal_moves, in_check}, search::{quiescence, INFINITY, PLY}, _csv_fen_to_bitboard::{find_columns, is_valid_fen, open_input, parse_evaluation}, Side};
+
+// Rows between two progress lines
+const PROGRESS_INTERVAL: usize = 100_000;
+
+#[derive(Default)]
+struct FilterCounts {
+    kept: usize,
+    replaced: usize,
+    tactical: usize,
+    in_check: usize,
+    invalid: usize,
+}
+
+// The side that just moved left its king in check, the search would capture it
+unsafe fn is_illegal(board: &Board) -> bool {
+    let mut opponent = board.clone();
+    opponent.side = if board.side == Side::White {Side::Black} else {Side::White};
+    in_check(&opponent)
+}
+
+// Static evaluation and quiescence result, side to move relative
+unsafe fn quiet_gap(board: &Board) -> i32 {
+    let mut board = board.clone();
+    (quiescence(&mut board, -INFINITY, INFINITY, 0) - evaluate(&board)).abs()
+}
+
+// Follows the best capture (or evasion) at every ply down to the position where standing pat is best
+unsafe fn quiescence_leaf(board: &Board) -> Board {
+    let mut board = board.clone();
+
+    for qs_ply in 0..32 {
+        let checked = in_check(&board);
+        let mut best_score = if checked {-INFINITY} else {evaluate(&board)};
+        let mut best_move = None;
+
+        let moves = generate_legal_moves(&board);
+        for c in 0..moves.count {
+            if !checked && !get_move_capture!(moves.moves[c]) { continue; }
+
+            let mut child = board.clone();
+            child.make_legal_move(moves.moves[c]);
+            PLY += 1;
+            let score = -quiescence(&mut child, -INFINITY, INFINITY, qs_ply + 1);
+            PLY -= 1;
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(moves.moves[c]);
+            }
+        }
+
+        match best_move {
+            Some(move_) => { board.make_move(move_, false); },
+            None => break,
+        }
+    }
+
+    board
+}
+
+pub unsafe fn filter_quiet(input: &str, output: &str, margin: i32, leaf: bool) -> Result<(), Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(open_input(input)?);
+    let headers = reader.headers()?.clone();
+    let (fen_column, evaluation_column) = find_columns(&headers);
+
+    let mut writer = WriterBuilder::new().flexible(true).from_path(output)?;
+    writer.write_record(&headers)?;
+
+    let start = Instant::now();
+    let mut counts = FilterCounts::default();
+    let mut rows = 0;
+
+    for record in reader.records() {
+        rows += 1;
+        if rows % PROGRESS_INTERVAL == 0 {
+            println!("Filtered {} rows, {} kept ({:.0} rows/s)", rows, counts.kept + counts.replaced, rows as f64 / start.elapsed().as_secs_f64());
+        }
+
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(_) => { counts.invalid += 1; continue; }
+        };
+        let fen = match (record.get(fen_column), record.get(evaluation_column)) {
+            (Some(fen), Some(evaluation)) if is_valid_fen(fen) && parse_evaluation(evaluation).is_some() => fen.trim(),
+            _ => { counts.invalid += 1; continue; }
+        };
+
+        let board = Board::new_from_fen(fen);
+        if is_illegal(&board) {
+            counts.invalid += 1;
+            continue;
+        }
+        if in_check(&board) {
+            counts.in_check += 1;
+            continue;
+        }
+
+        if quiet_gap(&board) <= margin {
+            counts.kept += 1;
+            writer.write_record(&record)?;
+            continue;
+        }
+
+        // Tactical, keep the quiet end of the capture sequence if there is one
+        let leaf_board = if leaf {Some(quiescence_leaf(&board))} else {None};
+        match leaf_board {
+            Some(leaf_board) if !in_check(&leaf_board) && quiet_gap(&leaf_board) <= margin => {
+                counts.replaced += 1;
+                let leaf_fen = leaf_board.to_fen();
+                writer.write_record(record.iter().enumerate().map(|(column, field)| if column == fen_column {leaf_fen.as_str()} else {field}))?;
+            },
+            _ => counts.tactical += 1,
+        }
+    }
+
+    writer.flush()?;
+    println!("Filtering complete: {} rows read in {:.1}s. Output saved to {}", rows, start.elapsed().as_secs_f64(), output);
+    println!("  kept quiet          {}", counts.kept);
+    if leaf {
+        println!("  replaced by leaf    {}", counts.replaced);
+    }
+    println!("  dropped tactical    {}", counts.tactical);
+    println!("  dropped in check    {}", counts.in_check);
+    println!("  dropped invalid     {}", counts.invalid);
+    Ok(())
+}
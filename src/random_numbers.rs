@@ -35,6 +35,26 @@ pub fn get_random_u64_number() -> u64 {
     n1 | (n2 << 16) | (n3 <<32) | (n4 << 48)
 }
 
+/*
+    Every xorshift32 output is a linear (GF(2)) function of the 32 bit seed, so keys
+    built from it only span 32 bits. Hash keys use xorshift64* instead, whose final
+    multiplication breaks the linearity.
+*/
+pub static mut KEY_STATE:u64 = 1070372;
+
+pub fn get_random_key() -> u64 {
+    let mut number:u64;
+    unsafe { number = KEY_STATE; };
+
+    number ^= number >> 12;
+    number ^= number << 25;
+    number ^= number >> 27;
+
+    unsafe { KEY_STATE = number; };
+
+    number.wrapping_mul(0x2545F4914F6CDD1D)
+}
+
 fn generate_magic_number() -> u64 {
     get_random_u64_number() & get_random_u64_number() & get_random_u64_number()
 }
@@ -1,4 +1,4 @@
-use crate::{eval::evaluate, bitboard::{Board, get_ls1b_index}, move_gen::{generate_moves, is_square_attacked}, Side, move_scoring::{sort_moves, KILLER_MOVES, HISTORY_MOVES, enbale_pv_scoring}, get_move_piece, get_move_target, get_move_capture, get_move_promoted, uci::{communicate, STOPPED}, transposition::{hash_flag, read_hash_entry, NO_HASH_ENTRY, write_hash_entry}, hashing::{ENPASSANT_KEYS, SIDE_KEY}};
+use crate::{eval::evaluate, bitboard::{Board, get_ls1b_index}, move_gen::{generate_moves, generate_legal_moves, is_square_attacked, in_check, gives_check}, Side, move_scoring::{sort_moves, sort_moves_scored, is_losing_capture, enbale_pv_scoring, quiet_history, update_quiet_cutoff, HISTORY_MAX, MOVE_STACK}, get_move_capture, get_move_promoted, uci::{communicate, STOPPED, NODE_LIMIT}, transposition::{hash_flag, read_hash_entry, NO_HASH_ENTRY, write_hash_entry}, hashing::{ENPASSANT_KEYS, SIDE_KEY}, linear_score::{LINEAR_ENPASSANT_SCORES, LINEAR_SIDE_SCORE}, syzygy::probe_search, endgame::probe_endgame};
 
 pub static mut NODES:u32 = 0;
 pub static mut PLY:usize = 0;
@@ -14,12 +14,23 @@ pub static mut PV_TABLE:[[usize;MAX_PLY];MAX_PLY] = [[0;MAX_PLY];MAX_PLY];
 pub static mut FOLLOW_PV:bool = false;
 pub static mut SCORE_PV:bool = false;
 
+// Search quiet checking moves at the first quiescence ply
+pub static mut QUIESCENCE_CHECKS:bool = true;
+
+// Nodes between GUI polls, a node limit is checked more often so it is not overshot by much
+const POLL_MASK:u32 = 65535;
+const NODE_LIMIT_POLL_MASK:u32 = 2047;
+
+fn poll_mask() -> u32 {
+    if unsafe { NODE_LIMIT }.is_some() { NODE_LIMIT_POLL_MASK } else { POLL_MASK }
+}
+
 // LMR
 const FULL_DEPTH_MOVES:u32 = 4;
 const REUCTION_LIMIT:i32 = 3;
 
 pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32) -> i32 {
-    if NODES & 65535 == 0 {
+    if NODES & poll_mask() == 0 {
         // Listen to GUI
         communicate();
     }
@@ -43,9 +54,16 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
     // Init PV
     PV_LENGTH[PLY] = PLY;
 
+    // Endgame tables (exact mate distances), then Syzygy WDL after zeroing moves. The root move comes from probe_root
+    if PLY != 0 {
+        if let Some(score) = probe_endgame(board).or_else(|| probe_search(board, depth)) {
+            return score
+        }
+    }
+
     if depth == 0 {
         // Run Quiescence search
-        return quiescence(board, alpha, beta)
+        return quiescence(board, alpha, beta, 0)
     }
 
     if PLY > MAX_PLY-1 {
@@ -63,6 +81,8 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
 
     // LMR
     let mut moves_searched:u32 = 0;
+    // Quiet moves searched without a cutoff, penalised if a later quiet fails high
+    let mut quiets_tried:Vec<usize> = Vec::new();
     // Temp vars
     let mut n_legal_moves:usize = 0;
 
@@ -70,6 +90,7 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
     if !(depth < 3) && !in_check && PLY!=0 {
         let current_board = board.clone();
         PLY += 1;
+        MOVE_STACK[PLY] = 0;
 
         // board.repetition_index += 1;
         // board.repetition_table[board.repetition_index] = board.hash_key;
@@ -77,8 +98,10 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
         // Hashing null move
         if let Some(square) = board.en_passant {
             board.hash_key ^= ENPASSANT_KEYS[square];
+            board.linear_score -= LINEAR_ENPASSANT_SCORES[square];
         }
         board.hash_key ^= SIDE_KEY;
+        board.linear_score += if board.side==Side::White {-LINEAR_SIDE_SCORE} else {LINEAR_SIDE_SCORE};
 
         // Swicth sides, giving opponent an extra move
         board.en_passant = None;
@@ -111,6 +134,7 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
     
     for c in 0..moves.count {
         let previous_board = board.clone();
+        let history = quiet_history(moves.moves[c], PLY);
         PLY += 1;
 
         // board.repetition_index += 1;
@@ -121,6 +145,7 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
             board.repetition_index -= 1;
             continue;
         }
+        MOVE_STACK[PLY] = moves.moves[c];
 
         // Current move score (static evaluation)
         let mut score: i32;
@@ -131,8 +156,11 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
         } else {
             // Condition to consider LMR
             if !(moves_searched < FULL_DEPTH_MOVES) && !(depth < REUCTION_LIMIT) 
-                    && !in_check && !get_move_capture!(moves.moves[c]) && get_move_promoted!(moves.moves[c]) > 11 {
-                score = -negamax(board, depth-2, -alpha - 1, -alpha);
+                    && !in_check && !get_move_capture!(moves.moves[c]) && get_move_promoted!(moves.moves[c]) > 11
+                    && history < HISTORY_MAX {
+                // Quiet moves with a bad history are reduced one ply more, a good one is not reduced
+                let reduction = if history < -HISTORY_MAX / 2 {2} else {1};
+                score = -negamax(board, depth-1-reduction, -alpha - 1, -alpha);
             } else {
                 // Hack to ensure full-depth search is done
                 score = alpha + 1;
@@ -176,10 +204,6 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
             // Switch flags
             hashf = hash_flag::Exact;
 
-            if !get_move_capture!(moves.moves[c]) {
-                HISTORY_MOVES[get_move_piece!(moves.moves[c])][get_move_target!(moves.moves[c])] += depth; //OVERFLOW??????????????????
-            }
-
             alpha = score;
 
             // Write PV move
@@ -194,15 +218,18 @@ pub unsafe fn negamax(board:&mut Board, mut depth:i32, mut alpha:i32, beta:i32)
                 write_hash_entry(board, beta, depth, hash_flag::Beta);
     
                 if !get_move_capture!(moves.moves[c]) {
-                    //Store killer moves
-                    KILLER_MOVES[1][PLY] = KILLER_MOVES[0][PLY];
-                    KILLER_MOVES[0][PLY] = moves.moves[c];
+                    //Store killer, counter move and histories
+                    update_quiet_cutoff(moves.moves[c], &quiets_tried, depth, PLY);
                 }
     
                 // Node fails high
                 return beta
             }
         }
+
+        if !get_move_capture!(moves.moves[c]) {
+            quiets_tried.push(moves.moves[c]);
+        }
     }
 
     // Checking for checkmate
@@ -228,9 +255,9 @@ pub fn is_repetition(board:&Board) -> bool {
     false
 }
 
-// Search for next captures
-pub unsafe fn quiescence(board:&mut Board, mut alpha:i32, beta:i32) -> i32 {
-    if NODES & 65535 == 0 {
+// Search for next captures (every evasion when in check, plus quiet checks on the first ply)
+pub unsafe fn quiescence(board:&mut Board, mut alpha:i32, beta:i32, qs_ply:usize) -> i32 {
+    if NODES & poll_mask() == 0 {
         // Listen to GUI
         communicate();
     }
@@ -241,34 +268,55 @@ pub unsafe fn quiescence(board:&mut Board, mut alpha:i32, beta:i32) -> i32 {
         return evaluate(board)
     }
 
-    let evaluation = evaluate(board);
-    if !(evaluation < beta) {
-        // Node fails high
-        return beta
-    }
+    // No standing pat when in check, every evasion has to be searched
+    let in_check = in_check(board);
+
+    if !in_check {
+        let evaluation = evaluate(board);
+        if !(evaluation < beta) {
+            // Node fails high
+            return beta
+        }
 
-    // Found a better move (PV node or move)
-    if evaluation > alpha {
-        alpha = evaluation;
+        // Found a better move (PV node or move)
+        if evaluation > alpha {
+            alpha = evaluation;
+        }
     }
 
-    let mut moves = generate_moves(board);
-    sort_moves(&mut moves, board);
+    let mut moves = if in_check {generate_legal_moves(board)} else {generate_moves(board)};
+    let move_scores = sort_moves_scored(&mut moves, board);
+
+    let mut n_legal_moves:usize = 0;
     
-    for c in 0..moves.count {
+    for (&move_, &move_score) in moves.moves.iter().zip(&move_scores) {
+        if !in_check {
+            if get_move_capture!(move_) {
+                // Prune captures that lose material, as found by SEE when sorting
+                if is_losing_capture(move_score) {
+                    continue;
+                }
+            } else if !(QUIESCENCE_CHECKS && qs_ply == 0 && gives_check(board, move_)) {
+                continue;
+            }
+        }
+
         let previous_board = board.clone();
         PLY += 1;
 
         // board.repetition_index += 1;
         // board.repetition_table[board.repetition_index] = board.hash_key;
 
-        if !board.make_move(moves.moves[c], true) {
+        if !board.make_move(move_, false) {
             PLY -= 1;
             board.repetition_index -= 1;
             continue;
         }
+        MOVE_STACK[PLY] = move_;
 
-        let score = -quiescence(board, -beta, -alpha);
+        n_legal_moves += 1;
+
+        let score = -quiescence(board, -beta, -alpha, qs_ply + 1);
 
         // Update vars
         PLY -= 1;
@@ -292,5 +340,10 @@ pub unsafe fn quiescence(board:&mut Board, mut alpha:i32, beta:i32) -> i32 {
         }
     }
 
+    // Checkmated while searching evasions
+    if in_check && n_legal_moves == 0 {
+        return -MATE_VALUE + (PLY as i32)
+    }
+
     alpha
 }
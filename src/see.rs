@@ -0,0 +1,162 @@
+/******************************************\
+ ==========================================
+        Static Exchange Evaluation
+ ==========================================
+\******************************************/
+
+use crate::{get_bit, get_move_capture, get_move_castling, get_move_enpassant, get_move_piece, get_move_promoted, get_move_source, get_move_target, Side};
+use crate::bitboard::{Board, get_ls1b_index};
+use crate::attacks::{get_bishop_attacks, get_rook_attacks};
+use crate::move_gen::attackers_to;
+
+// Piece values used for exchanges (P, N, B, R, Q, K)
+pub const SEE_PIECE_VALUE:[i32;6] = [100, 300, 350, 500, 1000, 10000];
+
+// Maximum length of an exchange sequence (32 pieces on the board)
+const MAX_EXCHANGES:usize = 32;
+
+#[inline(always)]
+fn piece_on(board:&Board, square:usize) -> Option<usize> {
+    (0..12).find(|&piece| get_bit!(board.bitboards[piece], square) != 0)
+}
+
+// Least valuable piece of a side among the attackers, as (square, piece)
+#[inline(always)]
+fn least_valuable_attacker(board:&Board, attackers:u64, white:bool) -> Option<(usize, usize)> {
+    let offset = if white {0} else {6};
+    for piece in offset..(offset+6) {
+        let bb = attackers & board.bitboards[piece];
+        if bb != 0 {
+            return Some((get_ls1b_index(bb), piece))
+        }
+    }
+    None
+}
+
+/*
+    Swap algorithm: every capture on the target square is played with the least
+    valuable attacker of the side to move, uncovering x-ray attackers (sliders
+    behind the piece that just captured) along the way. The gain list is then
+    evaluated backwards, letting each side stop the exchange when it is losing.
+
+    Returns the material balance of the exchange from the moving side's point of view.
+*/
+pub unsafe fn see(board:&Board, move_:usize) -> i32 {
+    if get_move_castling!(move_) {
+        return 0
+    }
+
+    let source_square = get_move_source!(move_);
+    let target_square = get_move_target!(move_);
+    let piece = get_move_piece!(move_);
+    let promoted = get_move_promoted!(move_);
+
+    let mut gain = [0i32; MAX_EXCHANGES];
+    let mut depth = 0;
+    let mut occupancy = board.occupancies[2];
+
+    // Value of the piece initially captured
+    if get_move_enpassant!(move_) {
+        let captured_square = if board.side == Side::White {target_square+8} else {target_square-8};
+        occupancy ^= 1u64 << captured_square;
+        gain[0] = SEE_PIECE_VALUE[0];
+    } else if get_move_capture!(move_) {
+        gain[0] = piece_on(board, target_square).map_or(0, |captured| SEE_PIECE_VALUE[captured % 6]);
+    }
+
+    // The piece standing on the target square after the move
+    let mut attacker_value = SEE_PIECE_VALUE[piece % 6];
+    if promoted < 12 {
+        gain[0] += SEE_PIECE_VALUE[promoted % 6] - SEE_PIECE_VALUE[0];
+        attacker_value = SEE_PIECE_VALUE[promoted % 6];
+    }
+
+    let bishops_queens = board.bitboards[2] | board.bitboards[4] | board.bitboards[8] | board.bitboards[10];
+    let rooks_queens = board.bitboards[3] | board.bitboards[4] | board.bitboards[9] | board.bitboards[10];
+
+    let mut from_bb = 1u64 << source_square;
+    let mut attackers = attackers_to(board, target_square, occupancy);
+    let mut white = board.side != Side::White;
+
+    loop {
+        depth += 1;
+        if depth >= MAX_EXCHANGES { break; }
+
+        // Speculative score if the piece on the target square gets captured
+        gain[depth] = attacker_value - gain[depth-1];
+
+        attackers &= !from_bb;
+        occupancy &= !from_bb;
+
+        // X-ray attackers uncovered through the vacated square
+        attackers |= get_bishop_attacks(target_square, occupancy) & bishops_queens;
+        attackers |= get_rook_attacks(target_square, occupancy) & rooks_queens;
+        attackers &= occupancy;
+
+        match least_valuable_attacker(board, attackers, white) {
+            Some((square, attacker)) => {
+                from_bb = 1u64 << square;
+                attacker_value = SEE_PIECE_VALUE[attacker % 6];
+            },
+            None => break,
+        }
+
+        white = !white;
+    }
+
+    while depth > 1 {
+        depth -= 1;
+        gain[depth-1] = -(-gain[depth-1]).max(gain[depth]);
+    }
+
+    gain[0]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uci::parse_move;
+
+    unsafe fn see_of(fen:&str, move_string:&str) -> i32 {
+        let board = Board::new_from_fen(fen);
+        let move_ = parse_move(&board, move_string);
+        assert_ne!(move_, 0, "{} is not legal in {}", move_string, fen);
+        see(&board, move_)
+    }
+
+    #[test]
+    fn queen_taking_a_defended_pawn_loses() {
+        crate::init_all_vars();
+        assert_eq!(unsafe { see_of("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "d1d5") }, 100 - 1000);
+    }
+
+    #[test]
+    fn xray_rook_wins_the_exchange() {
+        crate::init_all_vars();
+        unsafe {
+            // Rxd5 Rxd5 Rxd5, the d1 rook only attacks once d2 has moved
+            assert_eq!(see_of("4k3/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100);
+            assert_eq!(see_of("4k3/3r4/8/3p4/8/8/3R4/4K3 w - - 0 1", "d2d5"), 100 - 500);
+        }
+    }
+
+    #[test]
+    fn en_passant_captures_the_pushed_pawn() {
+        crate::init_all_vars();
+        unsafe {
+            assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+            assert_eq!(see_of("4k3/2p5/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 0);
+        }
+    }
+
+    #[test]
+    fn promotion_captures_count_the_new_piece() {
+        crate::init_all_vars();
+        unsafe {
+            assert_eq!(see_of("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"), 500 + 1000 - 100);
+            // Kxb8 takes the queen back
+            assert_eq!(see_of("1rk5/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"), 500 + 1000 - 100 - 1000);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+/******************************************\
+ ==========================================
+             Syzygy Tablebases
+ ==========================================
+\******************************************/
+
+/*
+    Syzygy WDL/DTZ probing from the SyzygyPath option: every directory (separated by
+    ':' or ';') is handed to shakmaty-syzygy, which reads the <material>.rtbw and
+    <material>.rtbz files and decodes them on demand.
+
+    Positions are built straight from the bitboards. The board does not keep the
+    fifty-move counter, so every probe assumes a zeroing move was just played: cursed
+    wins and blessed losses are scored as draws.
+
+    - At the root, probe_root picks the DTZ-optimal move and the search is skipped
+    - In the search, probe_search answers nodes reached by a capture or a pawn move,
+      at least SyzygyProbeDepth deep and with at most SyzygyProbeLimit pieces. Wins and
+      losses are placed just below MATE_SCORE (TB_WIN_SCORE - ply) so real mates still
+      sort above them and are not printed as "score mate"
+*/
+
+use std::ptr::addr_of;
+
+use shakmaty::{CastlingMode, Chess, Color, FromSetup, Piece, Role, Setup, Square};
+use shakmaty_syzygy::{Tablebase, Wdl};
+
+use crate::{Side, get_bit, pop_bit, get_move_capture, get_move_piece};
+use crate::bitboard::{Board, Pieces, count_bits, get_ls1b_index};
+use crate::move_gen::generate_legal_moves;
+use crate::moves::move_str;
+use crate::move_scoring::MOVE_STACK;
+use crate::search::{MATE_SCORE, MAX_PLY, PLY};
+
+pub static mut SYZYGY_PATH:Option<String> = None;
+pub static mut TABLEBASE:Option<Tablebase<Chess>> = None;
+// Pieces (kings included) of the largest table found
+pub static mut TB_LARGEST:usize = 0;
+// Tablebase probes answered during the current search
+pub static mut TB_HITS:u64 = 0;
+// Search probes: minimum remaining depth and most pieces (kings included)
+pub static mut SYZYGY_PROBE_DEPTH:i32 = 1;
+pub static mut SYZYGY_PROBE_LIMIT:usize = 7;
+
+const ROLES:[Role;6] = [Role::Pawn, Role::Knight, Role::Bishop, Role::Rook, Role::Queen, Role::King];
+
+pub const TB_WIN_SCORE:i32 = MATE_SCORE - MAX_PLY as i32;
+
+// SyzygyPath option, "<empty>" clears it
+pub unsafe fn set_syzygy_path(path:&str) {
+    SYZYGY_PATH = None;
+    TABLEBASE = None;
+    TB_LARGEST = 0;
+
+    if path.is_empty() || path == "<empty>" {
+        return
+    }
+
+    let mut tablebase = Tablebase::new();
+    let mut files = 0;
+    for directory in path.split([':', ';']).map(str::trim).filter(|directory| !directory.is_empty()) {
+        match tablebase.add_directory(directory) {
+            Ok(added) => files += added,
+            Err(e) => println!("info string cannot read {}: {}", directory, e),
+        }
+    }
+
+    TB_LARGEST = if files == 0 { 0 } else { tablebase.max_pieces() };
+    println!("info string found {} Syzygy files, up to {} pieces", files, { TB_LARGEST });
+
+    SYZYGY_PATH = Some(path.to_string());
+    if files != 0 {
+        TABLEBASE = Some(tablebase);
+    }
+}
+
+// Tables only cover positions without castling rights
+unsafe fn probe_position(board:&Board) -> Option<(&'static Tablebase<Chess>, Chess)> {
+    let tablebase = (*addr_of!(TABLEBASE)).as_ref()?;
+    if board.castle != 0 || count_bits(board.occupancies[2]) > TB_LARGEST.min(SYZYGY_PROBE_LIMIT) {
+        return None
+    }
+
+    // Engine squares run from a8 = 0, shakmaty ones from a1 = 0
+    let mut setup = Setup::empty();
+    for bb_piece in 0..12 {
+        let piece = Piece { color: if bb_piece < 6 {Color::White} else {Color::Black}, role: ROLES[bb_piece % 6] };
+        let mut bb = board.bitboards[bb_piece];
+        while bb != 0 {
+            let square = get_ls1b_index(bb);
+            setup.board.set_piece_at(Square::new((square ^ 56) as u32), piece);
+            pop_bit!(bb, square);
+        }
+    }
+    setup.turn = if board.side == Side::White {Color::White} else {Color::Black};
+    setup.ep_square = board.en_passant.map(|square| Square::new((square ^ 56) as u32));
+
+    let position = Chess::from_setup(setup, CastlingMode::Standard).ok()?;
+    Some((tablebase, position))
+}
+
+fn wdl_score(wdl:Wdl, ply:usize) -> i32 {
+    match wdl {
+        Wdl::Win => TB_WIN_SCORE - ply as i32,
+        Wdl::Loss => -TB_WIN_SCORE + ply as i32,
+        Wdl::CursedWin | Wdl::Draw | Wdl::BlessedLoss => 0,
+    }
+}
+
+// Side to move relative score of a position in the tables (None when not covered)
+pub unsafe fn probe_wdl(board:&Board) -> Option<i32> {
+    let (tablebase, position) = probe_position(board)?;
+    let wdl = tablebase.probe_wdl_after_zeroing(&position).ok()?;
+
+    TB_HITS += 1;
+    Some(wdl_score(wdl, PLY))
+}
+
+// In-search probe, only right after a zeroing move (capture or pawn move) with enough depth left
+pub unsafe fn probe_search(board:&Board, depth:i32) -> Option<i32> {
+    let last = MOVE_STACK[PLY];
+    if depth < SYZYGY_PROBE_DEPTH || last == 0 || (!get_move_capture!(last) && get_move_piece!(last) != Pieces::P as usize && get_move_piece!(last) != Pieces::p as usize) {
+        return None
+    }
+    probe_wdl(board)
+}
+
+// Tablebase-optimal root move and its score
+pub unsafe fn probe_root(board:&Board) -> Option<(usize, i32)> {
+    let (tablebase, position) = probe_position(board)?;
+    let wdl = tablebase.probe_wdl_after_zeroing(&position).ok()?;
+    let (best, _) = tablebase.best_move(&position).ok()??;
+
+    let best = best.to_uci(CastlingMode::Standard).to_string();
+    let moves = generate_legal_moves(board);
+    let move_ = moves.moves[..moves.count].iter().copied().find(|&move_| move_str(move_) == best)?;
+
+    TB_HITS += 1;
+    Some((move_, wdl_score(wdl, 0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard, Once};
+    use crate::move_gen::in_check;
+
+    // Holds KQvK.rtbw and KQvK.rtbz
+    const FIXTURES:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/syzygy");
+
+    // The tables and TB_HITS are globals: one test at a time
+    fn init() -> MutexGuard<'static, ()> {
+        static INIT:Once = Once::new();
+        static LOCK:Mutex<()> = Mutex::new(());
+        let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        INIT.call_once(|| unsafe {
+            crate::init_all_vars();
+            set_syzygy_path(FIXTURES);
+        });
+        assert!(unsafe { TB_LARGEST } != 0, "no Syzygy tables in {}", FIXTURES);
+        guard
+    }
+
+    #[test]
+    fn kqvk_is_won_for_the_queen_side() {
+        let _guard = init();
+        unsafe {
+            PLY = 0;
+            assert_eq!(probe_wdl(&Board::new_from_fen("8/8/3k4/8/8/8/8/Q3K3 w - - 0 1")), Some(TB_WIN_SCORE));
+            assert_eq!(probe_wdl(&Board::new_from_fen("8/8/3k4/8/8/8/8/Q3K3 b - - 0 1")), Some(-TB_WIN_SCORE));
+        }
+    }
+
+    #[test]
+    fn kvk_is_a_draw() {
+        let _guard = init();
+        unsafe {
+            PLY = 0;
+            assert_eq!(probe_wdl(&Board::new_from_fen("8/8/3k4/8/8/8/8/4K3 w - - 0 1")), Some(0));
+        }
+    }
+
+    #[test]
+    fn root_move_mates_in_one() {
+        let _guard = init();
+        unsafe {
+            let mut board = Board::new_from_fen("k7/8/1K6/8/8/8/7Q/8 w - - 0 1");
+            let (best, score) = probe_root(&board).expect("KQvK is in the tables");
+            assert_eq!(score, TB_WIN_SCORE);
+
+            // Qh8 is the only mate
+            assert!(board.make_move(best, false), "{} is not legal", move_str(best));
+            assert!(in_check(&board) && generate_legal_moves(&board).count == 0, "{} does not mate", move_str(best));
+        }
+    }
+
+    #[test]
+    fn tbhits_count_answered_probes() {
+        let _guard = init();
+        unsafe {
+            TB_HITS = 0;
+            probe_wdl(&Board::new_from_fen("8/8/3k4/8/8/8/8/Q3K3 w - - 0 1"));
+            probe_root(&Board::new_from_fen("8/8/3k4/8/8/8/8/Q3K3 w - - 0 1"));
+            assert_eq!({ TB_HITS }, 2);
+
+            // Not in the tables (too many pieces, castling rights) and not counted
+            assert_eq!(probe_wdl(&Board::new_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")), None);
+            assert_eq!(probe_wdl(&Board::new_from_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1")), None);
+            assert_eq!({ TB_HITS }, 2);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+/******************************************\
+ ==========================================
+              Texel Tuning
+ ==========================================
+\******************************************/
+
+/*
+    Tunes the linear model coefficients on game results. Every position is labelled
+    with the result of its game (1 White win, 0.5 draw, 0 Black win) and the model
+    score s (pawns) is mapped to an expected result sigmoid(K * s). K is fitted first
+    on the starting coefficients, then the coefficients minimize the mean squared
+    error between results and expected results with Adam.
+
+    Positions should be quiet (no pending captures or checks), the static score is
+    used as is.
+*/
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use crate::{bitboard::Board, eval::classical_linear_coefficients, _csv_fen_to_bitboard::{convert_board_to_csv, is_valid_fen, FEATURE_COUNT}, _linear_regression::{Dataset, LinearModel, LINEAR_MODEL_VERSION}};
+
+pub struct TexelOptions {
+    pub init: String,
+    pub k: Option<f64>,
+    pub epochs: usize,
+    pub learning_rate: f64,
+    pub validation: f64,
+}
+
+// Searched range of the sigmoid scale, in 1/pawns
+const K_RANGE: (f64, f64) = (0.01, 10.0);
+
+const ADAM_BETA1: f64 = 0.9;
+const ADAM_BETA2: f64 = 0.999;
+const ADAM_EPSILON: f64 = 1e-8;
+
+#[inline(always)]
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/*
+    A FEN (the first four fields are enough) followed by the result anywhere on the
+    line: 1-0, 0-1, 1/2-1/2, or 1.0, 0.5, 0.0, optionally quoted or in brackets
+    ("[0.5]", c9 "1-0";). Fields may also be comma separated, as datagen writes them.
+*/
+fn parse_labelled_position(line: &str) -> Option<(String, f64)> {
+    let tokens: Vec<&str> = line.split(|c: char| c.is_whitespace() || c == ',').filter(|token| !token.is_empty()).collect();
+    if tokens.len() < 5 {
+        return None
+    }
+
+    for token in &tokens[4..] {
+        let token = token.trim_matches(|c| matches!(c, '"' | ';' | '[' | ']' | ',' | '|'));
+        let result = match token {
+            "1-0" | "1.0" => 1.0,
+            "0-1" | "0.0" => 0.0,
+            "1/2-1/2" | "0.5" => 0.5,
+            _ => continue,
+        };
+        return Some((tokens[..4].join(" "), result))
+    }
+
+    None
+}
+
+fn read_positions(path: &str) -> Result<Dataset, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut dataset = Dataset::new(FEATURE_COUNT);
+    let mut skipped = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() { continue; }
+
+        match parse_labelled_position(&line) {
+            Some((fen, result)) if is_valid_fen(&fen) => dataset.push(&convert_board_to_csv(&Board::new_from_fen(&fen)), result),
+            _ => skipped += 1,
+        }
+    }
+
+    if skipped > 0 {
+        println!("Skipped {} lines without a valid position and result", skipped);
+    }
+    if dataset.len() == 0 {
+        return Err("no labelled positions".into())
+    }
+
+    Ok(dataset)
+}
+
+// Coefficients followed by the intercept
+fn initial_weights(init: &str) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut weights = match init {
+        "classical" => classical_linear_coefficients(),
+        "zero" => vec![0.0; FEATURE_COUNT],
+        path => {
+            let model = LinearModel::load(path)?;
+            let mut weights = model.coefficients;
+            // Version 1 models have no side to move, castling or en passant terms
+            weights.resize(FEATURE_COUNT, 0.0);
+            weights.push(model.intercept);
+            return Ok(weights)
+        }
+    };
+    weights.push(0.0);
+    Ok(weights)
+}
+
+fn texel_error(dataset: &Dataset, rows: &[usize], weights: &[f64], k: f64) -> f64 {
+    let total: f64 = rows.iter().map(|&row| {
+        let error = dataset.targets[row] - sigmoid(k * dataset.predict(weights, row));
+        error * error
+    }).sum();
+    total / rows.len().max(1) as f64
+}
+
+// Golden section search, the error is unimodal in K
+fn fit_k(dataset: &Dataset, rows: &[usize], weights: &[f64]) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = K_RANGE;
+
+    for _ in 0..60 {
+        let left = high - ratio * (high - low);
+        let right = low + ratio * (high - low);
+        if texel_error(dataset, rows, weights, left) < texel_error(dataset, rows, weights, right) {
+            high = right;
+        } else {
+            low = left;
+        }
+    }
+
+    (low + high) / 2.0
+}
+
+pub fn texel_tune(input: &str, output: &str, options: &TexelOptions) -> Result<(), Box<dyn Error>> {
+    let dataset = read_positions(input)?;
+    let (validation, train) = dataset.split(options.validation);
+    if train.is_empty() {
+        return Err("no training positions left after the validation split".into())
+    }
+    println!("Loaded {} positions ({} train, {} validation)", dataset.len(), train.len(), validation.len());
+
+    let mut weights = initial_weights(&options.init)?;
+    let k = options.k.unwrap_or_else(|| fit_k(&dataset, &train, &weights));
+    println!("K = {:.4} (per pawn)", k);
+    println!("epoch {:>5}: train error {:.6}  validation error {:.6}", 0, texel_error(&dataset, &train, &weights, k), texel_error(&dataset, &validation, &weights, k));
+
+    let n = weights.len();
+    let mut gradient = vec![0.0; n];
+    let mut first_moment = vec![0.0; n];
+    let mut second_moment = vec![0.0; n];
+    let report_every = (options.epochs / 20).max(1);
+
+    for epoch in 1..=options.epochs {
+        gradient.iter_mut().for_each(|g| *g = 0.0);
+
+        for &row in train.iter() {
+            let expected = sigmoid(k * dataset.predict(&weights, row));
+            let slope = -2.0 * (dataset.targets[row] - expected) * expected * (1.0 - expected) * k;
+            for (i, value) in dataset.row(row) {
+                gradient[i] += slope * value;
+            }
+            gradient[n - 1] += slope;
+        }
+
+        let bias_correction1 = 1.0 - ADAM_BETA1.powi(epoch as i32);
+        let bias_correction2 = 1.0 - ADAM_BETA2.powi(epoch as i32);
+        for i in 0..n {
+            let g = gradient[i] / train.len() as f64;
+            first_moment[i] = ADAM_BETA1 * first_moment[i] + (1.0 - ADAM_BETA1) * g;
+            second_moment[i] = ADAM_BETA2 * second_moment[i] + (1.0 - ADAM_BETA2) * g * g;
+            let step = (first_moment[i] / bias_correction1) / ((second_moment[i] / bias_correction2).sqrt() + ADAM_EPSILON);
+            weights[i] -= options.learning_rate * step;
+        }
+
+        if epoch % report_every == 0 || epoch == options.epochs {
+            println!("epoch {:>5}: train error {:.6}  validation error {:.6}", epoch, texel_error(&dataset, &train, &weights, k), texel_error(&dataset, &validation, &weights, k));
+        }
+    }
+
+    let model = LinearModel {
+        version: LINEAR_MODEL_VERSION,
+        coefficients: weights[..FEATURE_COUNT].to_vec(),
+        intercept: weights[FEATURE_COUNT],
+    };
+    let mut writer = BufWriter::new(File::create(output)?);
+    writer.write_all(serde_json::to_string_pretty(&model)?.as_bytes())?;
+    writer.flush()?;
+
+    println!("Model written to {}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let path = std::env::temp_dir().join(format!("texel-positions-{}.epd", std::process::id()));
+        std::fs::write(&path, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - c9 \"1/2-1/2\";\n\
+            not a fen at all 1-0\n\
+            rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 1-0\n").unwrap();
+        let dataset = read_positions(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dataset.unwrap().len(), 1);
+    }
+}
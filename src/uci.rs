@@ -1,15 +1,23 @@
 use std::io::{self, Write};
+use std::ptr::addr_of;
 use std::time::Duration;
 use std::time::Instant;
 
-use crate::eval::evaluate;
-use crate::move_scoring::{HISTORY_MOVES, KILLER_MOVES};
+use crate::eval::{evaluate, evaluator_name, set_evaluator, EVALUATOR_NAMES};
+use crate::nnue::load_network;
+use crate::_neural_network::load_eval_file;
+use crate::external_eval::{EXTERNAL_ADDRESS, EXTERNAL_TIMEOUT};
+use crate::move_scoring::clear_move_ordering;
 use crate::moves::move_str;
-use crate::search::{negamax, NODES, PV_TABLE, PV_LENGTH, MAX_PLY, FOLLOW_PV, SCORE_PV, INFINITY, MATE_SCORE, MATE_VALUE};
+use crate::search::{negamax, NODES, PV_TABLE, PV_LENGTH, MAX_PLY, FOLLOW_PV, SCORE_PV, INFINITY, MATE_SCORE, MATE_VALUE, QUIESCENCE_CHECKS};
 use crate::transposition::{HASH_TABLE, HASH_SIZE, tt};
 use crate::{get_move_source, get_move_target, get_move_promoted, START_POSITION, Side};
 use crate::bitboard::{Board, ASCII_PIECES};
-use crate::move_gen::generate_moves;
+use crate::move_gen::generate_legal_moves;
+use crate::bench::{run_bench, BENCH_DEPTH};
+use crate::syzygy::{set_syzygy_path, probe_root, TB_HITS, SYZYGY_PROBE_DEPTH, SYZYGY_PROBE_LIMIT};
+use crate::endgame::set_endgame_path;
+use crate::book::{book_move, book_mode_name, set_book_file, set_book_mode, BOOK_MODES, OWN_BOOK};
 
 
 // TIME CONTROL VARS
@@ -22,11 +30,12 @@ static mut TIME:i32 = -1;
 static mut INC:i32 = 0;
 static mut START_TIME:Option<Instant> = None;
 static mut STOP_TIME:Option<Duration> = None;
+pub static mut NODE_LIMIT:Option<u32> = None; // go nodes
 
 
 // Parse move string input from the GUI (e7e8q)
 pub unsafe fn parse_move(board:&Board, move_string:&str) -> usize {
-    let moves = generate_moves(board);
+    let moves = generate_legal_moves(board);
     
     let source_square = move_string.chars().nth(0).unwrap() as usize - 'a' as usize
                                 + (8 - (move_string.chars().nth(1).unwrap() as usize - '0' as usize)) * 8; 
@@ -70,7 +79,10 @@ pub fn parse_position(command:&str) -> Board {
         if complete_info.len() == 2 {
             let moves:Vec<&str> = complete_info[1].split_whitespace().collect();
             for move_ in moves {
-                board.make_move(parse_move(&board, move_), false);
+                let move_ = parse_move(&board, move_);
+                // Keep the position reached before an illegal move
+                if move_ == 0 { break; }
+                board.make_legal_move(move_);
                 board.repetition_table[index] = board.hash_key;
                 index += 1;
                 // println!("{}", index);
@@ -98,6 +110,7 @@ pub unsafe fn parse_go(board:&mut Board, command: &str) {
     INC = 0;
     START_TIME = None;
     STOP_TIME = None;
+    NODE_LIMIT = None;
 
     while let Some(&token) = iter.next() {
         match token {
@@ -150,6 +163,13 @@ pub unsafe fn parse_go(board:&mut Board, command: &str) {
                     }
                 }
             }
+            "nodes" => {
+                if let Some(&value) = iter.next() {
+                    if let Ok(nodes) = value.parse::<u32>() {
+                        NODE_LIMIT = Some(nodes);
+                    }
+                }
+            }
             "infinite" => {
                 TIMESET = false;
             }
@@ -194,7 +214,25 @@ pub unsafe fn parse_go_(board:&mut Board, command:&str) {
     search_position(board, depth);
 }
 
-pub fn search_position(board:&mut Board, depth:i32) {
+// Outcome of the last completed iteration
+pub struct SearchResult {
+    pub score:i32,
+    pub best_move:usize,
+    pub depth:i32,
+    pub nodes:u32,
+    pub iterations:Vec<(i32, usize, Duration)>, // Depth, best move and elapsed time of each completed iteration
+}
+
+// Time and node limits for searches not started by go
+pub unsafe fn set_search_limits(movetime_ms:Option<u64>, nodes:Option<u32>) {
+    START_TIME = Some(Instant::now());
+    TIMESET = movetime_ms.is_some();
+    STOP_TIME = movetime_ms.map(Duration::from_millis);
+    NODE_LIMIT = nodes;
+}
+
+// Iterative deepening up to depth (or until stopped), printing UCI info lines when verbose
+pub fn iterative_deepening(board:&mut Board, depth:i32, verbose:bool) -> SearchResult {
     unsafe {
         // Flags
         STOPPED = false;
@@ -203,11 +241,13 @@ pub fn search_position(board:&mut Board, depth:i32) {
 
         // Clear helper data
         NODES = 0;
-        KILLER_MOVES = [[0;MAX_PLY];2];
-        HISTORY_MOVES = [[0;64];12];
+        TB_HITS = 0;
+        clear_move_ordering();
         PV_TABLE = [[0;MAX_PLY];MAX_PLY];
         PV_LENGTH = [0;MAX_PLY];
 
+        let start = Instant::now();
+        let mut result = SearchResult { score: 0, best_move: 0, depth: 0, nodes: 0, iterations: Vec::new() };
         let mut alpha = -50000;
         let mut beta = 50000;
         let mut current_depth = 1;
@@ -222,6 +262,11 @@ pub fn search_position(board:&mut Board, depth:i32) {
 
             let score = negamax(board, current_depth, alpha, beta);
 
+            // Interrupted iterations are not trusted
+            if STOPPED {
+                break;
+            }
+
             // // Aspiration window
             // // If we fall outside the window, try again with full-width window (same depth)
             if !(score > alpha) || !(score<beta) {
@@ -233,33 +278,137 @@ pub fn search_position(board:&mut Board, depth:i32) {
             alpha = score - 50;
             beta = score + 50;
 
+            result.score = score;
+            result.best_move = PV_TABLE[0][0];
+            result.depth = current_depth;
+            result.nodes = NODES;
+            result.iterations.push((current_depth, PV_TABLE[0][0], start.elapsed()));
+
             // Print info for UCI
-            if score > -MATE_VALUE && score < -MATE_SCORE {
-                print!("info score mate {} depth {} nodes {} time {} ", -(score+MATE_VALUE)/2-1, current_depth, NODES, duration_as_ms(START_TIME.unwrap().elapsed()))
-            } else if score > MATE_SCORE && score < MATE_VALUE {
-                print!("info score mate {} depth {} nodes {} time {} ", (MATE_VALUE-score)/2-1, current_depth, NODES, duration_as_ms(START_TIME.unwrap().elapsed()))
-            } else {
-                match START_TIME {
-                    Some(value) => print!("info score cp {} depth {} nodes {} time {} ", score, current_depth, NODES, duration_as_ms(START_TIME.unwrap().elapsed())),
-                    None => print!("info score cp {} depth {} nodes {} ", score, current_depth, NODES),
+            if verbose {
+                if score > -MATE_VALUE && score < -MATE_SCORE {
+                    print!("info score mate {} depth {} nodes {} time {} ", -(score+MATE_VALUE)/2-1, current_depth, NODES, duration_as_ms(START_TIME.unwrap().elapsed()))
+                } else if score > MATE_SCORE && score < MATE_VALUE {
+                    print!("info score mate {} depth {} nodes {} time {} ", (MATE_VALUE-score)/2-1, current_depth, NODES, duration_as_ms(START_TIME.unwrap().elapsed()))
+                } else {
+                    match START_TIME {
+                        Some(value) => print!("info score cp {} depth {} nodes {} time {} ", score, current_depth, NODES, duration_as_ms(START_TIME.unwrap().elapsed())),
+                        None => print!("info score cp {} depth {} nodes {} ", score, current_depth, NODES),
+                    }
+                    
                 }
-                
-            }
 
-            print!("pv ");
-            for i in 0..PV_LENGTH[0] {
-                print!("{} ", move_str(PV_TABLE[0][i]));
+                print!("tbhits {} pv ", { TB_HITS });
+                for i in 0..PV_LENGTH[0] {
+                    print!("{} ", move_str(PV_TABLE[0][i]));
+                }
+                println!();
             }
-            println!();
 
             current_depth += 1;
         }
 
-        print!("bestmove ");
-        println!("{}", move_str(PV_TABLE[0][0]));
+        // Stopped before the first iteration completed
+        if result.best_move == 0 {
+            result.best_move = PV_TABLE[0][0];
+            result.nodes = NODES;
+        }
+
+        result
     }
 }
 
+pub fn search_position(board:&mut Board, depth:i32) {
+    // Book moves and tablebase positions are not searched
+    if let Some(best_move) = unsafe { book_move(board) } {
+        println!("info string book move");
+        println!("bestmove {}", move_str(best_move));
+        return
+    }
+    if let Some((best_move, score)) = unsafe { probe_root(board) } {
+        println!("info score cp {} depth 1 nodes 0 tbhits 1 pv {}", score, move_str(best_move));
+        println!("bestmove {}", move_str(best_move));
+        return
+    }
+
+    let result = iterative_deepening(board, depth, true);
+
+    print!("bestmove ");
+    println!("{}", move_str(result.best_move));
+}
+
+
+// Options the engine supports (advertised after "uci")
+pub unsafe fn print_options() {
+    println!("option name QuiescenceChecks type check default {}", { QUIESCENCE_CHECKS });
+    println!("option name Evaluator type combo default {} var {}", evaluator_name(), EVALUATOR_NAMES.join(" var "));
+    println!("option name NnueFile type string default <empty>");
+    println!("option name EvalFile type string default <empty>");
+    println!("option name ExternalEval type string default {}", (*addr_of!(EXTERNAL_ADDRESS)).as_deref().unwrap_or("<empty>"));
+    println!("option name ExternalTimeout type spin default {} min 1 max 60000", { EXTERNAL_TIMEOUT });
+    println!("option name SyzygyPath type string default <empty>");
+    println!("option name SyzygyProbeDepth type spin default {} min 1 max 100", { SYZYGY_PROBE_DEPTH });
+    println!("option name SyzygyProbeLimit type spin default {} min 0 max 7", { SYZYGY_PROBE_LIMIT });
+    println!("option name EndgamePath type string default <empty>");
+    println!("option name OwnBook type check default {}", { OWN_BOOK });
+    println!("option name BookFile type string default <empty>");
+    println!("option name BookMode type combo default {} var {}", book_mode_name(), BOOK_MODES.join(" var "));
+}
+
+// Parse setoption command (setoption name QuiescenceChecks value false)
+pub unsafe fn parse_option(command:&str) {
+    let info:Vec<&str> = command.splitn(2, " value ").collect();
+    let name = info[0].trim_start_matches("setoption").trim().trim_start_matches("name").trim();
+    let value = if info.len() == 2 {info[1].trim()} else {""};
+
+    // Option names are case insensitive
+    match name.to_lowercase().as_str() {
+        "quiescencechecks" => QUIESCENCE_CHECKS = value == "true",
+        "evaluator" => if let Err(e) = set_evaluator(value) {
+            println!("info string {}", e);
+        },
+        "nnuefile" => if let Err(e) = load_network(value) {
+            println!("info string failed to load NNUE network {}: {}", value, e);
+        },
+        "evalfile" => if let Err(e) = load_eval_file(value) {
+            println!("info string failed to load network {}: {}", value, e);
+        },
+        "externaleval" => {
+            EXTERNAL_ADDRESS = if value.is_empty() || value == "<empty>" {None} else {Some(value.to_string())};
+            // Reconnect with the new address
+            if evaluator_name() == "external" {
+                if let Err(e) = set_evaluator("external") {
+                    println!("info string {}", e);
+                }
+            }
+        },
+        "externaltimeout" => match value.parse() {
+            Ok(timeout) => {
+                EXTERNAL_TIMEOUT = timeout;
+                if evaluator_name() == "external" {
+                    let _ = set_evaluator("external");
+                }
+            },
+            Err(_) => println!("info string invalid ExternalTimeout {}", value),
+        },
+        "syzygypath" => set_syzygy_path(value),
+        "syzygyprobedepth" => match value.parse() {
+            Ok(depth) => SYZYGY_PROBE_DEPTH = depth,
+            Err(_) => println!("info string invalid SyzygyProbeDepth {}", value),
+        },
+        "syzygyprobelimit" => match value.parse() {
+            Ok(limit) => SYZYGY_PROBE_LIMIT = limit,
+            Err(_) => println!("info string invalid SyzygyProbeLimit {}", value),
+        },
+        "endgamepath" => set_endgame_path(value),
+        "ownbook" => OWN_BOOK = value == "true",
+        "bookfile" => set_book_file(value),
+        "bookmode" => if let Err(e) = set_book_mode(value) {
+            println!("info string {}", e);
+        },
+        _ => (),
+    }
+}
 
 pub unsafe fn uci_loop() {
     let mut input:String = String::new();
@@ -268,6 +417,7 @@ pub unsafe fn uci_loop() {
     // Enginge info
     println!("id name Optimus");
     println!("id author Simply's Adt");
+    print_options();
     println!("uciok");
 
     loop {
@@ -276,6 +426,12 @@ pub unsafe fn uci_loop() {
         let input_separated:Vec<&str> = input.split_whitespace().collect();
 
         match input_str {
+            x if x.starts_with("setoption") => parse_option(x),
+            // Extension: "bench [depth]"
+            x if x.starts_with("bench") => {
+                let depth = input_separated.get(1).and_then(|depth| depth.parse().ok()).unwrap_or(BENCH_DEPTH);
+                run_bench(depth);
+            },
             x if x.contains("isready") => {
                 STOPPED=true;
                 println!("readyok");
@@ -300,6 +456,7 @@ pub unsafe fn uci_loop() {
                 // Enginge info
                 println!("id name Optimus");
                 println!("id author Simply's Adt");
+                print_options();
                 println!("uciok");
             },
             x if x.contains("eval") => {
@@ -319,6 +476,9 @@ pub unsafe fn communicate() {
     if TIMESET && START_TIME.unwrap().elapsed() > STOP_TIME.unwrap() {
         STOPPED = true;
     }
+    if NODE_LIMIT.is_some_and(|limit| NODES >= limit) {
+        STOPPED = true;
+    }
 }
 
 
@@ -0,0 +1,434 @@
+/******************************************\
+ ==========================================
+               UCI Client
+ ==========================================
+\******************************************/
+
+/*
+    Drives a UCI engine running as a subprocess, for the tools that need another
+    engine (or a second copy of this one), such as match.
+
+    start() runs the handshake and collects the engine's id and options, set_option()
+    checks the name against them, go() sends the search limits and collects the info
+    lines until bestmove. Lines are read on their own thread (as in external_eval) so
+    every wait has a deadline and a hung engine cannot block the caller.
+*/
+
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Time allowed for the handshake and isready
+pub const HANDSHAKE_TIMEOUT:Duration = Duration::from_secs(10);
+
+// Program and arguments of an engine. A linear coefficients file is played by this
+// binary, "exec:command" runs any engine
+pub fn engine_command(spec:&str) -> io::Result<(String, Vec<String>)> {
+    match spec.strip_prefix("exec:") {
+        Some(command) => {
+            let mut parts = split_command(command).into_iter();
+            let program = parts.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty engine command"))?;
+            Ok((program, parts.collect()))
+        },
+        None => {
+            let args = ["uci", "-e", "linear", "-i", spec].map(str::to_string);
+            Ok((std::env::current_exe()?.display().to_string(), args.to_vec()))
+        },
+    }
+}
+
+// Splits on whitespace, except inside double quotes so paths may hold spaces
+fn split_command(command:&str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut in_part = false;
+    let mut quoted = false;
+    for c in command.chars() {
+        match c {
+            '"' => { quoted = !quoted; in_part = true; },
+            c if c.is_whitespace() && !quoted => if in_part {
+                parts.push(std::mem::take(&mut part));
+                in_part = false;
+            },
+            c => { part.push(c); in_part = true; },
+        }
+    }
+    if in_part {
+        parts.push(part);
+    }
+    parts
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Score {
+    Centipawns(i32),
+    Mate(i32), // Moves to mate, negative when getting mated
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+// "info" line, missing fields are None
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Info {
+    pub depth:Option<u32>,
+    pub seldepth:Option<u32>,
+    pub multipv:Option<u32>,
+    pub score:Option<(Score, Bound)>,
+    pub nodes:Option<u64>,
+    pub nps:Option<u64>,
+    pub time_ms:Option<u64>,
+    pub hashfull:Option<u32>,
+    pub pv:Vec<String>,
+    pub string:Option<String>,
+}
+
+impl Info {
+    pub fn parse(line:&str) -> Option<Info> {
+        let mut tokens = line.split_whitespace();
+        if tokens.next() != Some("info") {
+            return None
+        }
+
+        let mut info = Info::default();
+        while let Some(token) = tokens.next() {
+            match token {
+                "depth" => info.depth = tokens.next().and_then(|value| value.parse().ok()),
+                "seldepth" => info.seldepth = tokens.next().and_then(|value| value.parse().ok()),
+                "multipv" => info.multipv = tokens.next().and_then(|value| value.parse().ok()),
+                "nodes" => info.nodes = tokens.next().and_then(|value| value.parse().ok()),
+                "nps" => info.nps = tokens.next().and_then(|value| value.parse().ok()),
+                "time" => info.time_ms = tokens.next().and_then(|value| value.parse().ok()),
+                "hashfull" => info.hashfull = tokens.next().and_then(|value| value.parse().ok()),
+                "score" => {
+                    let kind = tokens.next();
+                    let value = tokens.next().and_then(|value| value.parse::<i32>().ok());
+                    info.score = match (kind, value) {
+                        (Some("cp"), Some(value)) => Some((Score::Centipawns(value), Bound::Exact)),
+                        (Some("mate"), Some(value)) => Some((Score::Mate(value), Bound::Exact)),
+                        _ => None,
+                    };
+                },
+                "lowerbound" => if let Some((score, _)) = info.score { info.score = Some((score, Bound::Lower)) },
+                "upperbound" => if let Some((score, _)) = info.score { info.score = Some((score, Bound::Upper)) },
+                // The rest of the line belongs to these two
+                "pv" => info.pv = tokens.by_ref().map(str::to_string).collect(),
+                "string" => info.string = Some(tokens.by_ref().collect::<Vec<_>>().join(" ")),
+                _ => (),
+            }
+        }
+
+        Some(info)
+    }
+}
+
+// "option name <name> type <type> [default <x>] [min <x>] [max <x>] [var <x>]*"
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct EngineOption {
+    pub name:String,
+    pub kind:String,
+    pub default:Option<String>,
+    pub min:Option<i64>,
+    pub max:Option<i64>,
+    pub vars:Vec<String>,
+}
+
+impl EngineOption {
+    pub fn parse(line:&str) -> Option<EngineOption> {
+        let line = line.trim().strip_prefix("option ")?;
+        let keywords = ["name", "type", "default", "min", "max", "var"];
+
+        // Values may hold spaces, they run until the next keyword
+        let mut option = EngineOption::default();
+        let mut key:Option<&str> = None;
+        let mut value:Vec<&str> = Vec::new();
+        for token in line.split_whitespace().chain(std::iter::once("var")) {
+            if !keywords.contains(&token) {
+                value.push(token);
+                continue;
+            }
+            let text = value.join(" ");
+            match key {
+                Some("name") => option.name = text,
+                Some("type") => option.kind = text,
+                Some("default") => option.default = Some(text),
+                Some("min") => option.min = text.parse().ok(),
+                Some("max") => option.max = text.parse().ok(),
+                Some("var") => option.vars.push(text),
+                _ => (),
+            }
+            key = Some(token);
+            value.clear();
+        }
+
+        if option.name.is_empty() { None } else { Some(option) }
+    }
+}
+
+// Limits of a "go" command
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct GoLimits {
+    pub depth:Option<u32>,
+    pub nodes:Option<u64>,
+    pub movetime:Option<u64>,
+    pub wtime:Option<u64>,
+    pub btime:Option<u64>,
+    pub winc:Option<u64>,
+    pub binc:Option<u64>,
+    pub movestogo:Option<u32>,
+    pub infinite:bool,
+}
+
+impl GoLimits {
+    pub fn command(&self) -> String {
+        let mut command = String::from("go");
+        let fields = [
+            ("wtime", self.wtime), ("btime", self.btime), ("winc", self.winc), ("binc", self.binc),
+            ("movestogo", self.movestogo.map(u64::from)), ("depth", self.depth.map(u64::from)),
+            ("nodes", self.nodes), ("movetime", self.movetime),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                command += &format!(" {} {}", name, value);
+            }
+        }
+        if self.infinite {
+            command += " infinite";
+        }
+        command
+    }
+}
+
+// Answer to a go command
+#[derive(Clone, PartialEq, Debug)]
+pub struct SearchReport {
+    pub best_move:String,
+    pub ponder:Option<String>,
+    pub info:Vec<Info>,
+}
+
+impl SearchReport {
+    // Score of the deepest search, side to move relative
+    #[allow(dead_code)]
+    pub fn score(&self) -> Option<Score> {
+        self.info.iter().rev()
+            .filter(|info| info.multipv.is_none_or(|multipv| multipv == 1))
+            .find_map(|info| info.score.map(|(score, _)| score))
+    }
+}
+
+pub struct UciEngine {
+    pub name:String,
+    pub author:String,
+    pub options:Vec<EngineOption>,
+    child:Child,
+    stdin:BufWriter<ChildStdin>,
+    lines:Receiver<String>,
+}
+
+impl UciEngine {
+    // Starts the program with its arguments and runs the handshake
+    pub fn start(program:&str, args:&[String]) -> Result<UciEngine, Box<dyn Error>> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("cannot start {}: {}", program, e))?;
+        let stdin = BufWriter::new(child.stdin.take().ok_or("no stdin")?);
+        let stdout = BufReader::new(child.stdout.take().ok_or("no stdout")?);
+
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let mut engine = UciEngine { name: program.to_string(), author: String::new(), options: Vec::new(), child, stdin, lines };
+        engine.send("uci")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = engine.read_line(deadline)?;
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if let Some(author) = line.strip_prefix("id author ") {
+                engine.author = author.trim().to_string();
+            } else if let Some(option) = EngineOption::parse(line) {
+                engine.options.push(option);
+            } else if line == "uciok" {
+                break;
+            }
+        }
+        engine.is_ready()?;
+
+        Ok(engine)
+    }
+
+    pub fn send(&mut self, command:&str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    pub fn read_line(&self, deadline:Instant) -> Result<String, Box<dyn Error>> {
+        match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err(Box::new(io::Error::new(io::ErrorKind::TimedOut, "engine timed out"))),
+            Err(RecvTimeoutError::Disconnected) => Err("engine exited".into()),
+        }
+    }
+
+    // Waits for readyok, skipping anything sent before (such as a late bestmove)
+    pub fn is_ready(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send("isready")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while self.read_line(deadline)?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    // Option names are case insensitive, unknown names are refused
+    #[allow(dead_code)]
+    pub fn set_option(&mut self, name:&str, value:&str) -> Result<(), Box<dyn Error>> {
+        let option = self.options.iter().find(|option| option.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("{} has no option {}", self.name, name))?;
+        let command = if option.kind == "button" {format!("setoption name {}", option.name)} else {format!("setoption name {} value {}", option.name, value)};
+        self.send(&command)?;
+        self.is_ready()
+    }
+
+    pub fn new_game(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send("ucinewgame")?;
+        self.is_ready()
+    }
+
+    // Start position when fen is None
+    pub fn set_position(&mut self, fen:Option<&str>, moves:&[String]) -> io::Result<()> {
+        let mut command = match fen {
+            Some(fen) => format!("position fen {}", fen),
+            None => String::from("position startpos"),
+        };
+        if !moves.is_empty() {
+            command += " moves ";
+            command += &moves.join(" ");
+        }
+        self.send(&command)
+    }
+
+    // Searches the current position, collecting the info lines until bestmove
+    pub fn go(&mut self, limits:&GoLimits, timeout:Duration) -> Result<SearchReport, Box<dyn Error>> {
+        self.send(&limits.command())?;
+
+        let deadline = Instant::now() + timeout;
+        let mut info = Vec::new();
+        loop {
+            let line = self.read_line(deadline)?;
+            if let Some(line_info) = Info::parse(&line) {
+                info.push(line_info);
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            if tokens.next() == Some("bestmove") {
+                let best_move = tokens.next().ok_or("bestmove without a move")?.to_string();
+                let ponder = match (tokens.next(), tokens.next()) {
+                    (Some("ponder"), Some(ponder)) => Some(ponder.to_string()),
+                    _ => None,
+                };
+                return Ok(SearchReport { best_move, ponder, info })
+            }
+        }
+    }
+
+    pub fn stop(&mut self) -> io::Result<()> {
+        self.send("stop")
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_bounds_apply_to_the_score() {
+        let info = Info::parse("info depth 12 seldepth 18 multipv 1 score cp 35 lowerbound nodes 120000 nps 800000 time 150 hashfull 12").unwrap();
+        assert_eq!(info.depth, Some(12));
+        assert_eq!(info.seldepth, Some(18));
+        assert_eq!(info.multipv, Some(1));
+        assert_eq!(info.score, Some((Score::Centipawns(35), Bound::Lower)));
+        assert_eq!((info.nodes, info.nps, info.time_ms, info.hashfull), (Some(120000), Some(800000), Some(150), Some(12)));
+
+        let info = Info::parse("info depth 20 score mate -3 upperbound").unwrap();
+        assert_eq!(info.score, Some((Score::Mate(-3), Bound::Upper)));
+        assert_eq!(Info::parse("bestmove e2e4"), None);
+    }
+
+    #[test]
+    fn info_pv_and_string_run_to_the_end_of_the_line() {
+        let info = Info::parse("info depth 3 score cp 20 pv e2e4 e7e5 g1f3").unwrap();
+        assert_eq!(info.pv, ["e2e4", "e7e5", "g1f3"]);
+
+        // Keywords inside the string are part of it
+        let info = Info::parse("info string depth 4 score cp from the book").unwrap();
+        assert_eq!(info.string.as_deref(), Some("depth 4 score cp from the book"));
+        assert_eq!((info.depth, info.score), (None, None));
+    }
+
+    #[test]
+    fn option_names_may_hold_spaces() {
+        let option = EngineOption::parse("option name Clear Hash type button").unwrap();
+        assert_eq!((option.name.as_str(), option.kind.as_str()), ("Clear Hash", "button"));
+
+        let option = EngineOption::parse("option name Move Overhead type spin default 10 min 0 max 5000").unwrap();
+        assert_eq!(option.name, "Move Overhead");
+        assert_eq!(option.default.as_deref(), Some("10"));
+        assert_eq!((option.min, option.max), (Some(0), Some(5000)));
+
+        let option = EngineOption::parse("option name Style type combo default Very Solid var Very Solid var Risky").unwrap();
+        assert_eq!(option.default.as_deref(), Some("Very Solid"));
+        assert_eq!(option.vars, ["Very Solid", "Risky"]);
+
+        assert_eq!(EngineOption::parse("option type check default true"), None);
+    }
+
+    #[test]
+    fn go_command_lists_the_set_limits() {
+        assert_eq!(GoLimits::default().command(), "go");
+        assert_eq!(GoLimits { depth: Some(8), ..GoLimits::default() }.command(), "go depth 8");
+
+        let limits = GoLimits { wtime: Some(60000), btime: Some(55000), winc: Some(1000), binc: Some(1000), movestogo: Some(20), ..GoLimits::default() };
+        assert_eq!(limits.command(), "go wtime 60000 btime 55000 winc 1000 binc 1000 movestogo 20");
+        assert_eq!(GoLimits { infinite: true, ..GoLimits::default() }.command(), "go infinite");
+    }
+
+    #[test]
+    fn engine_paths_may_hold_spaces() {
+        let (program, args) = engine_command(r#"exec:"/opt/my engines/stockfish" --threads 2"#).unwrap();
+        assert_eq!(program, "/opt/my engines/stockfish");
+        assert_eq!(args, ["--threads", "2"]);
+
+        let (_, args) = engine_command("my models/linear.json").unwrap();
+        assert_eq!(args.last().map(String::as_str), Some("my models/linear.json"));
+
+        assert!(engine_command("exec: ").is_err());
+    }
+}